@@ -1,4 +1,5 @@
 use nih_plug::prelude::*;
+use modular_host::{is_panic, HeadlessPlugin, HostContext, NihContext};
 use std::sync::{Arc};
 
 mod sequence;
@@ -62,6 +63,11 @@ pub struct EuclidianRhythm{
 pub struct Euclidian {
     params: Arc<EuclidianParams>,
     rhythms: [EuclidianRhythm;4],
+    /// the note and velocity each voice is playing
+    sounding: [Option<(u8, f32)>;4],
+    playing: bool,
+    /// the notes are ended at the start of the next block after a reset
+    release_pending: bool,
 }
 
 #[derive(Params)]
@@ -149,6 +155,9 @@ impl Default for Euclidian {
         Self {
             params: Arc::new(EuclidianParams::default()),
             rhythms: Default::default(),
+            sounding: [None;4],
+            playing: false,
+            release_pending: false,
         }
     }
 }
@@ -207,30 +216,45 @@ impl Euclidian {
         }
     }
 
-    fn sample_sequence(context: &mut impl ProcessContext<Euclidian>, note: u8, velocity: i32, sample_position: i64, sequence: &Sequence) {
+    fn sample_sequence(context: &mut impl HostContext<Euclidian>, note: u8, velocity: i32, timing: u32, sample_position: i64,
+                       sequence: &Sequence, sounding: &mut Option<(u8, f32)>) {
         let wrapped_sample_position = sequence.get_wrapped_sample_position(sample_position);
         for event in sequence.get_note_events_at_sample(wrapped_sample_position).iter() {
+            // a NoteOff ends the note that is sounding, even if the note parameter has changed in the meantime
+            Self::release_note(context, timing, sounding);
             if event.note_data {
                 context.send_event(NoteEvent::NoteOn {
-                    timing: sample_position as u32,
-
-                    voice_id: None,
-                    channel: 0,
-                    note: note,
-                    velocity: velocity as f32 / 127.0,
-                });
-            } else {
-                context.send_event(NoteEvent::NoteOff {
-                    timing: sample_position as u32,
+                    timing,
 
                     voice_id: None,
                     channel: 0,
                     note: note,
                     velocity: velocity as f32 / 127.0,
                 });
+                *sounding = Some((note, velocity as f32 / 127.0));
             }
         }
     }
+
+    fn release_note(context: &mut impl HostContext<Euclidian>, timing: u32, sounding: &mut Option<(u8, f32)>) {
+        if let Some((note, velocity)) = sounding.take() {
+            context.send_event(NoteEvent::NoteOff {
+                timing,
+
+                voice_id: None,
+                channel: 0,
+                note,
+                velocity,
+            });
+        }
+    }
+
+    /// Ends the notes of all voices, e.g. on a panic message.
+    fn release_notes(&mut self, context: &mut impl HostContext<Euclidian>, timing: u32) {
+        for sounding in self.sounding.iter_mut() {
+            Self::release_note(context, timing, sounding);
+        }
+    }
 }
 
 
@@ -254,8 +278,8 @@ impl Plugin for Euclidian {
         aux_outputs: None,
     };
 
-    // No MIDI input needed
-    const MIDI_INPUT: MidiConfig = MidiConfig::Basic;
+    // MIDI input is only used for the panic messages
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
 
     const MIDI_OUTPUT: MidiConfig = MidiConfig::Basic;
 
@@ -270,25 +294,51 @@ impl Plugin for Euclidian {
         self.params.clone()
     }
 
-    fn initialize(&mut self, bus_config: &BusConfig, buffer_config: &BufferConfig, context: &mut impl InitContext<Self>) -> bool {
+    fn initialize(&mut self, _bus_config: &BusConfig, _buffer_config: &BufferConfig, _context: &mut impl InitContext<Self>) -> bool {
         true
     }
 
+    fn reset(&mut self) {
+        // the host cannot receive events here
+        self.release_pending = true;
+        self.playing = false;
+    }
+
     fn process(
         &mut self,
-        _buffer: &mut Buffer,
+        buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        self.process_events(buffer.samples() as u32, &mut NihContext::new(context))
+    }
+}
+
+impl HeadlessPlugin for Euclidian {
+    fn process_events(&mut self, num_samples: u32, context: &mut impl HostContext<Self>) -> ProcessStatus {
         // pretty inefficient to calculate this on every execution
 
         // Check if the transport is playing and if the time signature information is available
-        if context.transport().playing {
-            let tempo = context.transport().tempo.unwrap_or(120.0);
-            let sample_rate = context.transport().sample_rate;
+        let transport = context.transport();
+
+        // the notes end after a reset, when the transport stops and when their voice is disabled
+        if self.release_pending || (self.playing && !transport.playing) {
+            self.release_notes(context, 0);
+            self.release_pending = false;
+        }
+        self.playing = transport.playing;
+        for (voice_params, sounding) in self.params.voice_params.iter().zip(self.sounding.iter_mut()) {
+            if !voice_params.enabled.value() {
+                Self::release_note(context, 0, sounding);
+            }
+        }
+
+        if transport.playing {
+            let tempo = transport.tempo.unwrap_or(120.0);
+            let sample_rate = transport.sample_rate;
 
             // get the step length in quarters
-            for (voice_params, mut euclidian) in self.params.voice_params.iter().zip(self.rhythms.iter_mut()){
+            for (voice_params, euclidian) in self.params.voice_params.iter().zip(self.rhythms.iter_mut()){
                 if voice_params.enabled.value() {
                     euclidian.rhythm = self::euclidean_rhythm(
                         voice_params.num_notes.value() as usize,
@@ -298,14 +348,27 @@ impl Plugin for Euclidian {
                     Self::update_sequence(&mut euclidian.sequence, &euclidian.rhythm, voice_params.num_steps.value() as i64, voice_params.step_size.value().get_value(), tempo, sample_rate);
                 }
             }
+        }
 
-            let sample_position_start = context.transport().pos_samples().unwrap_or(0) as i64;
+        let sample_position_start = transport.pos_samples.unwrap_or(0);
+        let mut next_event = context.next_event();
+
+        for i in 0..num_samples {
+            // panic messages end the notes at their sample
+            while let Some(event) = next_event.filter(|event| event.timing() <= i) {
+                if is_panic(&event) {
+                    self.release_notes(context, i);
+                }
+                next_event = context.next_event();
+            }
 
-            for i in 0.._buffer.samples() {
+            if transport.playing {
                 let sample_position = sample_position_start + i as i64;
-                for (voice_params, euclidian) in self.params.voice_params.iter().zip(self.rhythms.iter()) {
+                let voices = self.params.voice_params.iter().zip(self.rhythms.iter()).zip(self.sounding.iter_mut());
+                for ((voice_params, euclidian), sounding) in voices {
                     if voice_params.enabled.value() {
-                        Self::sample_sequence(context, voice_params.note.value() as u8, voice_params.velocity.value(), sample_position, &euclidian.sequence);
+                        Self::sample_sequence(context, voice_params.note.value() as u8, voice_params.velocity.value(), i,
+                                              sample_position, &euclidian.sequence, sounding);
                     }
                 }
             }
@@ -333,3 +396,88 @@ nih_export_clap!(Euclidian);
 nih_export_vst3!(Euclidian);
 
 
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::prelude::*;
+    use modular_host::{set_parameter, TestHost};
+    use crate::Euclidian;
+
+    fn note_on(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn { timing, voice_id: None, channel: 0, note, velocity: 1.0 }
+    }
+
+    fn note_off(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOff { timing, voice_id: None, channel: 0, note, velocity: 1.0 }
+    }
+
+    #[test]
+    fn test_process() {
+        let mut host = TestHost::new(512);
+        let mut plugin = Euclidian::default();
+        assert!(host.initialize(&mut plugin));
+
+        // nothing is played while the transport is stopped
+        host.run(&mut plugin, 1024);
+        assert!(host.output_events().is_empty());
+
+        // two notes in eight 1/8 steps at 120 bpm, a step is 11025 samples long
+        host.transport.playing = true;
+        host.run(&mut plugin, 88_200);
+        assert_eq!(host.output_events(), [
+            note_on(1024, 36),
+            note_off(1024 + 11_025, 36),
+            note_on(1024 + 44_100, 36),
+            note_off(1024 + 55_125, 36),
+        ]);
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut host = TestHost::new(512);
+        let mut plugin = Euclidian::default();
+        assert!(host.initialize(&mut plugin));
+
+        // the note ends at the sample of the panic message, the end of its step does not send another NoteOff
+        host.transport.playing = true;
+        host.queue_event(NoteEvent::MidiCC { timing: 1500, channel: 0, cc: 123, value: 0.0 });
+        host.run(&mut plugin, 12_000);
+        assert_eq!(host.output_events(), [note_on(0, 36), note_off(1500, 36)]);
+    }
+
+    #[test]
+    fn test_transport_stop_and_reset() {
+        let mut host = TestHost::new(512);
+        let mut plugin = Euclidian::default();
+        assert!(host.initialize(&mut plugin));
+
+        host.transport.playing = true;
+        host.run(&mut plugin, 512);
+        host.transport.playing = false;
+        host.run(&mut plugin, 512);
+        assert_eq!(host.take_output_events(), [note_on(0, 36), note_off(512, 36)]);
+
+        host.transport.playing = true;
+        host.transport.pos_samples = Some(0);
+        host.run(&mut plugin, 512);
+        plugin.reset();
+        host.run(&mut plugin, 512);
+        assert_eq!(host.take_output_events(), [note_on(1024, 36), note_off(1536, 36)]);
+    }
+
+    #[test]
+    fn test_process_follows_transport_position() {
+        let mut host = TestHost::new(512);
+        let mut plugin = Euclidian::default();
+        assert!(host.initialize(&mut plugin));
+        assert!(set_parameter(&plugin, "num_notes_voice__1", 1.0));
+
+        // the sequence restarts at the position of the transport, not at the start of processing
+        host.transport.playing = true;
+        host.transport.pos_samples = Some(88_000);
+        host.run(&mut plugin, 1000);
+        assert_eq!(host.output_events(), [note_on(200, 36)]);
+    }
+}
@@ -0,0 +1,15 @@
+#![no_main]
+
+//! Plays fuzzer input as a session of chord and pattern events and parameter changes into Modular::Patterns and checks
+//! that no note is left hanging, like the property tests in `tests/state_machine.rs`.
+
+use libfuzzer_sys::fuzz_target;
+use modular_host::{check_session, SessionStep};
+use modular_patterns::Patterns;
+
+fuzz_target!(|data: &[u8]| {
+    let mut plugin = Patterns::default();
+    if let Err(error) = check_session(&mut plugin, &SessionStep::from_bytes(data), 64) {
+        panic!("{}", error);
+    }
+});
@@ -13,7 +13,7 @@ pub struct ActiveNoteChordIndex {
     pub octave: i32,
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct ActiveNoteDefaultData {
     /// A unique identifier for this note, if available. Using this to refer to a note is
     /// required when allowing overlapping voices for CLAP plugins.
@@ -40,13 +40,62 @@ impl ActiveNoteDefaultData {
 
 pub type HeldNotes <Index: Ord = ActiveNoteDefaultIndex, Data = ActiveNoteDefaultData> = BTreeMap<Index, Data>;
 
+/// Number of distinct MIDI keys.
+pub const NUM_KEYS: usize = 128;
+
+/// Held notes indexed by their MIDI key. Unlike [HeldNotes] it never allocates, so it can be used on the audio thread.
+/// Iteration is in ascending key order.
+#[derive(Debug, Clone)]
+pub struct HeldKeys<Data: Copy> {
+    keys: [Option<Data>; NUM_KEYS],
+}
+
+impl<Data: Copy> Default for HeldKeys<Data> {
+    fn default() -> Self {
+        Self {
+            keys: [None; NUM_KEYS],
+        }
+    }
+}
+
+impl<Data: Copy> HeldKeys<Data> {
+    pub fn get(&self, key: u8) -> Option<&Data> {
+        self.keys.get(key as usize).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, key: u8) -> Option<&mut Data> {
+        self.keys.get_mut(key as usize).and_then(Option::as_mut)
+    }
+
+    /// Holds the key and returns the data it was held with before. Keys outside the MIDI range are ignored.
+    pub fn insert(&mut self, key: u8, data: Data) -> Option<Data> {
+        self.keys.get_mut(key as usize).and_then(|slot| slot.replace(data))
+    }
+
+    pub fn remove(&mut self, key: u8) -> Option<Data> {
+        self.keys.get_mut(key as usize).and_then(Option::take)
+    }
+
+    pub fn clear(&mut self) {
+        self.keys = [None; NUM_KEYS];
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &Data)> {
+        self.keys.iter().enumerate().filter_map(|(key, slot)| slot.as_ref().map(|data| (key as u8, data)))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u8, &mut Data)> {
+        self.keys.iter_mut().enumerate().filter_map(|(key, slot)| slot.as_mut().map(|data| (key as u8, data)))
+    }
+}
+
 
 // Tests
 // -------------------------------------------------------------------------------------------------
 
 #[cfg(test)]
 mod tests {
-    use crate::active_note::{ActiveNoteDefaultData, ActiveNoteChordIndex, HeldNotes};
+    use crate::active_note::{ActiveNoteDefaultData, ActiveNoteChordIndex, HeldNotes, HeldKeys};
 
     #[test]
     fn test_sorting() {
@@ -141,4 +190,36 @@ mod tests {
         assert_eq!(held_keys_index, keys);
         assert_eq!(held_keys_values, values);
     }
+
+    #[test]
+    fn test_held_keys() {
+        let mut held_keys = HeldKeys::<ActiveNoteDefaultData>::default();
+        assert_eq!(held_keys.iter().count(), 0);
+
+        let data = ActiveNoteDefaultData {
+            voice_id: None,
+            channel: 1,
+            note: 64,
+            velocity: 0.5,
+        };
+
+        assert_eq!(held_keys.insert(64, data), None);
+        assert_eq!(held_keys.insert(60, ActiveNoteDefaultData { note: 60, ..data }), None);
+        assert_eq!(held_keys.insert(64, data), Some(data)); // already held
+
+        // out of MIDI range
+        assert_eq!(held_keys.insert(200, data), None);
+        assert!(held_keys.get(200).is_none());
+
+        assert_eq!(held_keys.iter().count(), 2);
+        let keys: Vec<u8> = held_keys.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, [60, 64]);
+
+        held_keys.get_mut(60).unwrap().velocity = 1.0;
+        assert_eq!(held_keys.get(60).unwrap().velocity, 1.0);
+
+        assert_eq!(held_keys.remove(64), Some(data));
+        assert_eq!(held_keys.remove(64), None);
+        assert_eq!(held_keys.iter().count(), 1);
+    }
 }
@@ -0,0 +1,302 @@
+use crate::chord_analysis::analyze;
+use crate::utils::ChordOrder;
+
+/// There are only 128 MIDI notes, so a chord can never hold more notes than this.
+pub const MAX_CHORD_NOTES: usize = 128;
+
+/// The held chord notes in the order they arrived, stored on the stack so it can be changed on the audio thread
+/// without allocating.
+#[derive(Clone, Debug)]
+pub struct Chord {
+    notes: [u8; MAX_CHORD_NOTES],
+    len: usize,
+    /// the velocity of each note of the chord, indexed by the note. None for notes that were not played with one.
+    velocities: [Option<f32>; MAX_CHORD_NOTES],
+}
+
+impl Default for Chord {
+    fn default() -> Self {
+        Self {
+            notes: [0; MAX_CHORD_NOTES],
+            len: 0,
+            velocities: [None; MAX_CHORD_NOTES],
+        }
+    }
+}
+
+impl PartialEq for Chord {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for Chord {
+    fn from(notes: [u8; N]) -> Self {
+        let mut chord = Chord::default();
+        for note in notes {
+            chord.insert(note);
+        }
+        chord
+    }
+}
+
+impl Chord {
+    /// Adds a note after the other notes of the chord. Returns false if the note was already part of the chord.
+    pub fn insert(&mut self, note: u8) -> bool {
+        self.insert_with_velocity(note, None)
+    }
+
+    /// Adds a note and its velocity after the other notes of the chord. Returns false if the note was already part of
+    /// the chord, it keeps its velocity then.
+    pub fn insert_with_velocity(&mut self, note: u8, velocity: Option<f32>) -> bool {
+        if self.contains(note) || self.len == MAX_CHORD_NOTES {
+            return false;
+        }
+        self.notes[self.len] = note;
+        self.velocities[velocity_idx(note)] = velocity;
+        self.len += 1;
+        true
+    }
+
+    /// Removes a note from the chord. Returns false if the note was not part of the chord.
+    pub fn remove(&mut self, note: u8) -> bool {
+        match self.as_slice().iter().position(|n| *n == note) {
+            Some(idx) => {
+                self.notes.copy_within(idx + 1..self.len, idx);
+                self.len -= 1;
+                self.velocities[velocity_idx(note)] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces a note while keeping its position and velocity. If the new note is already part of the chord the note
+    /// is removed.
+    pub fn replace(&mut self, note: u8, new_note: u8) {
+        if new_note == note {
+            return;
+        }
+        if self.contains(new_note) {
+            self.remove(note);
+        } else if let Some(idx) = self.as_slice().iter().position(|n| *n == note) {
+            self.notes[idx] = new_note;
+            self.velocities[velocity_idx(new_note)] = self.velocities[velocity_idx(note)].take();
+        }
+    }
+
+    /// The velocity of a note of the chord.
+    pub fn velocity(&self, note: u8) -> Option<f32> {
+        if self.contains(note) { self.velocities[velocity_idx(note)] } else { None }
+    }
+
+    pub fn set_velocity(&mut self, note: u8, velocity: Option<f32>) {
+        if self.contains(note) {
+            self.velocities[velocity_idx(note)] = velocity;
+        }
+    }
+
+    /// Gives each note the velocity of the note at the same position in `other`.
+    pub fn copy_velocities(&mut self, other: &Chord) {
+        for (note, other_note) in self.notes[..self.len].iter().zip(other.iter()) {
+            self.velocities[velocity_idx(*note)] = other.velocity(*other_note);
+        }
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        self.as_slice().contains(&note)
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.notes[..self.len]
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, u8> {
+        self.as_slice().iter()
+    }
+
+    /// Sorts the notes ascending.
+    pub fn sort(&mut self) {
+        self.notes[..self.len].sort_unstable();
+    }
+
+    pub fn lowest(&self) -> Option<u8> {
+        self.iter().min().copied()
+    }
+
+    /// Writes the notes of the chord to `ordered` in the order the chord indices are assigned.
+    pub fn order(&self, chord_order: ChordOrder, ordered: &mut Chord) {
+        ordered.clone_from(self);
+        let notes = &mut ordered.notes[..ordered.len];
+
+        match chord_order {
+            ChordOrder::Ascending => notes.sort_unstable(),
+            ChordOrder::Descending => notes.sort_unstable_by(|a, b| b.cmp(a)),
+            ChordOrder::Arrival => {}
+            ChordOrder::RootFirst => {
+                notes.sort_unstable();
+                if let Some(root_idx) = self.root().and_then(|root| notes.iter().position(|n| *n == root)) {
+                    notes[..=root_idx].rotate_right(1);
+                }
+            }
+            // the lowest note followed by the other notes in arrival order
+            ChordOrder::BassSeparated => {
+                if let Some(bass_idx) = notes.iter().enumerate().min_by_key(|(_, n)| **n).map(|(idx, _)| idx) {
+                    notes[..=bass_idx].rotate_right(1);
+                }
+            }
+        }
+    }
+
+    /// The lowest note of the root the chord analysis finds, or the lowest note if the notes are no chord it knows.
+    pub fn root(&self) -> Option<u8> {
+        match analyze(self) {
+            Some(analysis) => self.iter().copied().filter(|note| note % 12 == analysis.root).min(),
+            None => self.lowest(),
+        }
+    }
+}
+
+/// The index of the velocity of a note. Notes beyond the MIDI range never sound, they may share one.
+fn velocity_idx(note: u8) -> usize {
+    note as usize % MAX_CHORD_NOTES
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::utils::ChordOrder;
+
+    fn ordered(chord: &Chord, chord_order: ChordOrder) -> Vec<u8> {
+        let mut ordered = Chord::default();
+        chord.order(chord_order, &mut ordered);
+        ordered.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut chord = Chord::default();
+        assert!(chord.is_empty());
+
+        assert!(chord.insert(64));
+        assert!(chord.insert(60));
+        assert!(chord.insert(67));
+        assert!(!chord.insert(60)); // already part of the chord
+
+        assert_eq!(chord.as_slice(), [64, 60, 67]);
+        assert_eq!(chord.len(), 3);
+        assert!(chord.contains(64));
+        assert_eq!(chord.lowest(), Some(60));
+
+        assert!(chord.remove(64));
+        assert!(!chord.remove(64)); // not part of the chord anymore
+        assert_eq!(chord.as_slice(), [60, 67]);
+
+        chord.replace(60, 72);
+        assert_eq!(chord.as_slice(), [72, 67]);
+        chord.replace(67, 67); // the note itself
+        assert_eq!(chord.as_slice(), [72, 67]);
+        chord.replace(67, 72); // already part of the chord
+        assert_eq!(chord.as_slice(), [72]);
+
+        chord.clear();
+        assert!(chord.is_empty());
+        assert_eq!(chord.lowest(), None);
+    }
+
+    #[test]
+    fn test_velocities() {
+        let mut chord = Chord::default();
+        chord.insert_with_velocity(60, Some(0.5));
+        chord.insert_with_velocity(48, Some(0.25));
+        chord.insert(64);
+        assert!(!chord.insert_with_velocity(60, Some(1.0))); // keeps its velocity
+        assert_eq!(chord.velocity(60), Some(0.5));
+        assert_eq!(chord.velocity(64), None);
+
+        // a replaced note keeps its velocity, its octave does not share it
+        chord.replace(48, 36);
+        assert_eq!(chord.velocity(36), Some(0.25));
+        assert_eq!(chord.velocity(48), None);
+
+        let mut ordered = Chord::default();
+        chord.order(ChordOrder::Descending, &mut ordered);
+        assert_eq!(ordered.as_slice(), [64, 60, 36]);
+        assert_eq!(ordered.velocity(36), Some(0.25));
+
+        // a removed note forgets its velocity
+        chord.remove(60);
+        chord.insert(60);
+        assert_eq!(chord.velocity(60), None);
+
+        // the velocities go by position
+        let mut other = Chord::from([40, 41]);
+        other.copy_velocities(&ordered);
+        assert_eq!(other.velocity(40), None);
+        assert_eq!(other.velocity(41), Some(0.5));
+    }
+
+    #[test]
+    fn test_all_notes() {
+        let mut chord = Chord::default();
+        for note in (0..128).rev() {
+            assert!(chord.insert(note));
+        }
+        let notes: Vec<u8> = (0..128).collect();
+        assert_eq!(ordered(&chord, ChordOrder::Ascending), notes);
+    }
+
+    #[test]
+    fn test_eq_ignores_removed_notes() {
+        let mut chord = Chord::from([60, 64, 67]);
+        chord.remove(67);
+        assert_eq!(chord, Chord::from([60, 64]));
+    }
+
+    #[test]
+    fn test_root() {
+        assert_eq!(Chord::default().root(), None);
+        assert_eq!(Chord::from([60]).root(), Some(60));
+        assert_eq!(Chord::from([60, 64, 67]).root(), Some(60)); // C major
+        assert_eq!(Chord::from([64, 67, 72]).root(), Some(72)); // C major, first inversion
+        assert_eq!(Chord::from([55, 60, 64]).root(), Some(60)); // C major, second inversion
+        assert_eq!(Chord::from([57, 60, 64]).root(), Some(57)); // A minor
+        assert_eq!(Chord::from([62, 65, 69, 72]).root(), Some(62)); // D minor 7
+        assert_eq!(Chord::from([60, 62]).root(), Some(60)); // C sus2 without its fifth
+    }
+
+    #[test]
+    fn test_order() {
+        // E3, C3, G3, C2 in this order
+        let chord = Chord::from([64, 60, 67, 48]);
+
+        assert_eq!(ordered(&chord, ChordOrder::Ascending), [48, 60, 64, 67]);
+        assert_eq!(ordered(&chord, ChordOrder::Descending), [67, 64, 60, 48]);
+        assert_eq!(ordered(&chord, ChordOrder::Arrival), [64, 60, 67, 48]);
+        assert_eq!(ordered(&chord, ChordOrder::RootFirst), [48, 60, 64, 67]);
+        assert_eq!(ordered(&chord, ChordOrder::BassSeparated), [48, 64, 60, 67]);
+
+        // first inversion: the root is moved to the front
+        let chord = Chord::from([64, 67, 72]);
+        assert_eq!(ordered(&chord, ChordOrder::RootFirst), [72, 64, 67]);
+
+        assert!(ordered(&Chord::default(), ChordOrder::RootFirst).is_empty());
+        assert!(ordered(&Chord::default(), ChordOrder::BassSeparated).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use crate::chord::Chord;
+use crate::chord_symbol::{ChordQuality, CHORD_DICTIONARY};
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// What the notes of a chord most likely are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChordAnalysis {
+    /// the pitch class of the root, 0 is C
+    pub root: u8,
+    /// the index into [CHORD_DICTIONARY]
+    pub quality: usize,
+    /// the chord tone in the bass: 0 for the root, 1 for the third, 2 for the fifth and so on. None if the bass is not
+    /// a chord tone.
+    pub inversion: Option<u8>,
+    /// the lowest note
+    pub bass: u8,
+}
+
+impl ChordAnalysis {
+    pub fn quality(&self) -> &'static ChordQuality {
+        &CHORD_DICTIONARY[self.quality]
+    }
+}
+
+impl fmt::Display for ChordAnalysis {
+    /// The chord symbol, e.g. `C`, `Am7` or `G7/B`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quality = match self.quality().name {
+            "maj" => "",
+            "min" => "m",
+            name => name,
+        };
+        write!(f, "{}{}", NOTE_NAMES[self.root as usize], quality)?;
+        if self.inversion != Some(0) {
+            write!(f, "/{}", NOTE_NAMES[(self.bass % 12) as usize])?;
+        }
+        Ok(())
+    }
+}
+
+fn pitch_classes(root: u8, quality: &ChordQuality) -> u16 {
+    quality.intervals.iter().fold(0, |classes, interval| classes | (1 << ((root + interval) % 12)))
+}
+
+/// How well a root and quality explain the pitch classes of a chord. Every matching tone counts, every missing or
+/// extra tone costs the same. A missing perfect fifth costs little, voicings often leave it out. The root has to be
+/// part of the chord, in the bass it counts extra.
+fn score(classes: u16, bass_class: u8, root: u8, quality: &ChordQuality) -> Option<i32> {
+    if classes & (1 << root) == 0 {
+        return None;
+    }
+
+    let quality_classes = pitch_classes(root, quality);
+    let missing = quality_classes & !classes;
+    let missing_fifth = quality.intervals.contains(&7) && missing & (1 << ((root + 7) % 12)) != 0;
+
+    let matched = (classes & quality_classes).count_ones() as i32;
+    let extra = (classes & !quality_classes).count_ones() as i32;
+    let missing_other = missing.count_ones() as i32 - missing_fifth as i32;
+    Some(4 * (matched - extra - missing_other) - missing_fifth as i32 + 2 * (root == bass_class) as i32)
+}
+
+/// Names the chord: root, quality, inversion and bass. Incomplete voicings are named after the chord they most likely
+/// belong to, ambiguous ones like C6 and Am7 after the one with its root in the bass, then after the simpler quality.
+/// A chord needs at least two pitch classes.
+pub fn analyze(chord: &Chord) -> Option<ChordAnalysis> {
+    let bass = chord.lowest()?;
+    let classes = chord.iter().fold(0u16, |classes, note| classes | (1 << (note % 12)));
+    if classes.count_ones() < 2 {
+        return None;
+    }
+
+    let mut best: Option<(i32, ChordAnalysis)> = None;
+    for root in 0..12 {
+        for (quality_idx, quality) in CHORD_DICTIONARY.iter().enumerate() {
+            let score = match score(classes, bass % 12, root, quality) {
+                Some(score) => score,
+                None => continue,
+            };
+            if best.is_some_and(|(best_score, _)| best_score >= score) {
+                continue;
+            }
+            let bass_interval = (bass % 12 + 12 - root) % 12;
+            best = Some((score, ChordAnalysis {
+                root,
+                quality: quality_idx,
+                inversion: quality.intervals.iter().position(|interval| interval % 12 == bass_interval)
+                    .map(|position| position as u8),
+                bass,
+            }));
+        }
+    }
+    best.map(|(_, analysis)| analysis)
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::chord_analysis::analyze;
+
+    fn name<const N: usize>(notes: [u8; N]) -> Option<String> {
+        analyze(&Chord::from(notes)).map(|analysis| analysis.to_string())
+    }
+
+    #[test]
+    fn test_triads() {
+        assert_eq!(name([60, 64, 67]), Some("C".to_string()));
+        assert_eq!(name([57, 60, 64]), Some("Am".to_string()));
+        assert_eq!(name([59, 62, 65]), Some("Bdim".to_string()));
+        assert_eq!(name([60, 64, 68]), Some("Caug".to_string()));
+        assert_eq!(name([60, 65, 67]), Some("Csus4".to_string()));
+        assert_eq!(name([65, 67, 72]), Some("Fsus2".to_string()));
+    }
+
+    #[test]
+    fn test_extended() {
+        assert_eq!(name([55, 59, 62, 65]), Some("G7".to_string()));
+        assert_eq!(name([62, 65, 69, 72]), Some("Dm7".to_string()));
+        assert_eq!(name([59, 62, 65, 69]), Some("Bm7b5".to_string()));
+        assert_eq!(name([60, 64, 67, 71]), Some("Cmaj7".to_string()));
+        assert_eq!(name([60, 64, 67, 74]), Some("Cadd9".to_string()));
+        assert_eq!(name([60, 64, 67, 70, 74]), Some("C9".to_string()));
+    }
+
+    #[test]
+    fn test_inversions() {
+        let analysis = analyze(&Chord::from([64, 67, 72])).unwrap();
+        assert_eq!((analysis.root, analysis.inversion, analysis.bass), (0, Some(1), 64));
+        assert_eq!(analysis.to_string(), "C/E");
+
+        assert_eq!(analyze(&Chord::from([55, 60, 64])).unwrap().inversion, Some(2));
+        assert_eq!(name([53, 55, 59, 62]), Some("G7/F".to_string()));
+        // the bass is not part of the chord
+        assert_eq!(analyze(&Chord::from([54, 60, 64, 67])).unwrap().inversion, None);
+        assert_eq!(name([54, 60, 64, 67]), Some("C/F#".to_string()));
+    }
+
+    #[test]
+    fn test_incomplete_and_ambiguous() {
+        // without the fifth
+        assert_eq!(name([60, 64, 70]), Some("C7".to_string()));
+        assert_eq!(name([60, 64]), Some("C".to_string()));
+        // the same pitch classes, the bass decides
+        assert_eq!(name([60, 64, 67, 69]), Some("C6".to_string()));
+        assert_eq!(name([57, 60, 64, 67]), Some("Am7".to_string()));
+        // symmetric chords are named after their bass
+        assert_eq!(name([63, 66, 69, 72]), Some("D#dim7".to_string()));
+        assert_eq!(name([64, 68, 72]), Some("Eaug".to_string()));
+        // a single pitch class is not a chord
+        assert_eq!(name([60, 72]), None);
+        assert_eq!(name([]), None);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::chord::Chord;
+
+/// Turns the keys of the chord channel into the chord the patterns follow. Besides the held keys it can latch the last
+/// chord and bridge short gaps and legato overlaps between chords. All times are absolute sample positions.
+#[derive(Clone, Debug, Default)]
+pub struct ChordInput {
+    /// keys that are held on the chord channel
+    keys: Chord,
+    /// released keys of the current chord that stay in the chord while latch is on
+    latched: Chord,
+    /// released keys that stay in the chord until `release_deadline` to bridge a gap to the next chord
+    released: Chord,
+    release_deadline: Option<u64>,
+    /// held keys of the previous chord that are left out while they are being released
+    superseded: Chord,
+    /// held keys of the previous chord that are superseded as soon as one of them is released before the deadline
+    previous: Chord,
+    /// keys that are pressed while the previous chord is held. They join the chord when it is superseded or at the
+    /// deadline.
+    pending: Chord,
+    superseded_deadline: Option<u64>,
+    last_note_on: Option<u64>,
+    last_note_off: Option<u64>,
+    /// every note that was pressed, in the order of the last time it was pressed, with the velocity it was pressed with
+    arrival: Chord,
+    /// whether the last key was pressed while no other key was held
+    pressed_alone: bool,
+    /// released keys that the sustain pedal keeps in the chord
+    sustained: Chord,
+    sustain: bool,
+    /// the notes of the last chord symbol, they sound alongside the keys
+    symbol: Chord,
+}
+
+impl ChordInput {
+    pub fn note_on(&mut self, note: u8, velocity: f32, now: u64, latch: bool, bridge_samples: u64) {
+        self.expire(now);
+
+        let starts_new_chord = match self.last_note_on {
+            Some(last_note_on) => now.saturating_sub(last_note_on) > bridge_samples,
+            None => true,
+        };
+
+        if bridge_samples > 0 && starts_new_chord && !self.keys.is_empty() {
+            // a legato change: the previous chord is replaced once one of its keys is released within the bridge time,
+            // before or after the new chord starts. Otherwise the new notes are added to the held chord after the
+            // bridge time. Keys that are still held after the bridge time are taken back into the chord.
+            self.previous = self.keys.clone();
+            self.superseded_deadline = Some(now + bridge_samples);
+            self.latched.clear();
+            if self.last_note_off.is_some_and(|last_note_off| now.saturating_sub(last_note_off) <= bridge_samples) {
+                self.supersede();
+            }
+        }
+
+        if !latch || self.keys.is_empty() {
+            // a new chord replaces the latched and bridged notes
+            self.latched.clear();
+            self.released.clear();
+            self.release_deadline = None;
+        }
+
+        self.pressed_alone = self.keys.is_empty();
+        self.keys.insert(note);
+        self.superseded.remove(note);
+        if !self.previous.is_empty() {
+            self.pending.insert(note);
+        }
+        self.sustained.remove(note);
+        self.last_note_on = Some(now);
+
+        self.arrival.remove(note);
+        self.arrival.insert_with_velocity(note, Some(velocity));
+    }
+
+    pub fn note_off(&mut self, note: u8, now: u64, latch: bool, bridge_samples: u64) {
+        self.expire(now);
+
+        if !self.keys.remove(note) {
+            return;
+        }
+        self.last_note_off = Some(now);
+        if self.previous.remove(note) {
+            // the previous chord is being released, the new chord replaces it
+            self.supersede();
+            return;
+        }
+        if self.superseded.remove(note) || self.pending.remove(note) {
+            return;
+        }
+
+        if self.sustain {
+            self.sustained.insert(note);
+        } else {
+            self.release(note, now, latch, bridge_samples);
+        }
+    }
+
+    fn supersede(&mut self) {
+        self.superseded = std::mem::take(&mut self.previous);
+        self.pending.clear();
+    }
+
+    fn release(&mut self, note: u8, now: u64, latch: bool, bridge_samples: u64) {
+        if latch {
+            self.latched.insert(note);
+        } else if bridge_samples > 0 {
+            self.released.insert(note);
+            self.release_deadline = Some(now + bridge_samples);
+        }
+    }
+
+    /// Presses or releases the sustain pedal. Releasing it releases the keys it kept in the chord.
+    pub fn set_sustain(&mut self, sustain: bool, now: u64, latch: bool, bridge_samples: u64) {
+        self.expire(now);
+        self.sustain = sustain;
+
+        if !sustain {
+            let sustained = std::mem::take(&mut self.sustained);
+            for note in sustained.iter().copied() {
+                self.release(note, now, latch, bridge_samples);
+            }
+        }
+    }
+
+    /// Ends the bridges whose time is up.
+    pub fn expire(&mut self, now: u64) {
+        if self.release_deadline.is_some_and(|deadline| deadline <= now) {
+            self.released.clear();
+            self.release_deadline = None;
+        }
+        if self.superseded_deadline.is_some_and(|deadline| deadline <= now) {
+            self.superseded.clear();
+            self.previous.clear();
+            self.pending.clear();
+            self.superseded_deadline = None;
+        }
+    }
+
+    /// The next time a bridge ends and the chord changes without any input.
+    pub fn next_deadline(&self) -> Option<u64> {
+        match (self.release_deadline, self.superseded_deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Whether the chord comes from a single key that was pressed on its own, not from a chord that is released note by
+    /// note.
+    pub fn is_single_key(&self) -> bool {
+        self.pressed_alone
+    }
+
+    pub fn is_latched(&self) -> bool {
+        !self.latched.is_empty()
+    }
+
+    /// Drops the latched notes, e.g. when latch is turned off.
+    pub fn unlatch(&mut self) {
+        self.latched.clear();
+    }
+
+    pub fn set_symbol(&mut self, symbol: &Chord) {
+        self.symbol.clone_from(symbol);
+    }
+
+    /// Writes the chord that is currently sounding, in the order the notes arrived and with the velocity they were
+    /// pressed with, followed by the notes of the chord symbol.
+    pub fn update_chord(&self, chord: &mut Chord) {
+        chord.clear();
+        for note in self.arrival.iter().copied() {
+            let sounding = self.keys.contains(note) || self.latched.contains(note) || self.released.contains(note)
+                || self.sustained.contains(note);
+            if sounding && !self.superseded.contains(note) && !self.pending.contains(note) {
+                chord.insert_with_velocity(note, self.arrival.velocity(note));
+            }
+        }
+        for note in self.symbol.iter().copied() {
+            chord.insert(note);
+        }
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::chord_input::ChordInput;
+
+    fn chord_of(chord_input: &ChordInput) -> Vec<u8> {
+        let mut chord = Chord::default();
+        chord_input.update_chord(&mut chord);
+        chord.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_held_keys() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 0);
+        chord_input.note_on(64, 1.0, 0, false, 0);
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+
+        chord_input.note_off(60, 100, false, 0);
+        assert_eq!(chord_of(&chord_input), [64]);
+
+        chord_input.note_off(64, 100, false, 0);
+        assert!(chord_of(&chord_input).is_empty());
+        assert_eq!(chord_input.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, true, 0);
+        chord_input.note_on(64, 1.0, 0, true, 0);
+        chord_input.note_off(60, 100, true, 0);
+        chord_input.note_off(64, 110, true, 0);
+
+        // the chord stays after all keys are released
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+        assert!(chord_input.is_latched());
+
+        // until a new chord is played
+        chord_input.note_on(62, 1.0, 1000, true, 0);
+        assert_eq!(chord_of(&chord_input), [62]);
+        chord_input.note_on(65, 1.0, 1000, true, 0);
+        assert_eq!(chord_of(&chord_input), [62, 65]);
+
+        chord_input.note_off(62, 2000, true, 0);
+        chord_input.note_off(65, 2000, true, 0);
+        chord_input.unlatch();
+        assert!(chord_of(&chord_input).is_empty());
+    }
+
+    #[test]
+    fn test_bridge_gap() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 100);
+        chord_input.note_on(64, 1.0, 0, false, 100);
+        chord_input.note_off(60, 1000, false, 100);
+        chord_input.note_off(64, 1000, false, 100);
+
+        // released notes are kept for the bridge time
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+        assert_eq!(chord_input.next_deadline(), Some(1100));
+
+        // and replaced by the next chord within the bridge time
+        chord_input.note_on(62, 1.0, 1050, false, 100);
+        assert_eq!(chord_of(&chord_input), [62]);
+        assert_eq!(chord_input.next_deadline(), None);
+
+        // without a new chord the notes are released after the bridge time
+        chord_input.note_off(62, 2000, false, 100);
+        assert_eq!(chord_of(&chord_input), [62]);
+        chord_input.expire(2099);
+        assert_eq!(chord_of(&chord_input), [62]);
+        chord_input.expire(2100);
+        assert!(chord_of(&chord_input).is_empty());
+    }
+
+    #[test]
+    fn test_bridge_overlap() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 100);
+        chord_input.note_on(64, 1.0, 10, false, 100); // notes within the bridge time belong to the same chord
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+
+        // the next chord is pressed before the previous one is released. It waits for a key of the previous chord to
+        // be released.
+        chord_input.note_on(62, 1.0, 1000, false, 100);
+        chord_input.note_on(65, 1.0, 1000, false, 100);
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+
+        chord_input.note_off(60, 1020, false, 100);
+        assert_eq!(chord_of(&chord_input), [62, 65]);
+
+        // a key of the previous chord that is held on is taken back after the bridge time
+        chord_input.expire(1100);
+        assert_eq!(chord_of(&chord_input), [64, 62, 65]);
+    }
+
+    #[test]
+    fn test_bridge_release_before_overlap() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 100);
+        chord_input.note_on(64, 1.0, 0, false, 100);
+
+        // a key of the previous chord is released just before the next chord starts
+        chord_input.note_off(60, 990, false, 100);
+        chord_input.note_on(62, 1.0, 1000, false, 100);
+        assert_eq!(chord_of(&chord_input), [62]);
+
+        chord_input.note_off(64, 1010, false, 100);
+        assert_eq!(chord_of(&chord_input), [62]);
+        chord_input.expire(1100);
+        assert_eq!(chord_of(&chord_input), [62]);
+    }
+
+    #[test]
+    fn test_bridge_added_notes() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 100);
+        chord_input.note_on(64, 1.0, 0, false, 100);
+
+        // a note added to a held chord, or a slowly rolled chord, keeps the held notes. It joins the chord after the
+        // bridge time.
+        chord_input.note_on(67, 1.0, 1000, false, 100);
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+        assert_eq!(chord_input.next_deadline(), Some(1100));
+        chord_input.expire(1100);
+        assert_eq!(chord_of(&chord_input), [60, 64, 67]);
+        chord_input.note_on(72, 1.0, 2000, false, 100);
+        chord_input.expire(2100);
+        assert_eq!(chord_of(&chord_input), [60, 64, 67, 72]);
+
+        // a key that is released after the bridge time only bridges the gap to the next chord
+        chord_input.note_off(60, 2200, false, 100);
+        assert_eq!(chord_of(&chord_input), [60, 64, 67, 72]);
+        chord_input.expire(2300);
+        assert_eq!(chord_of(&chord_input), [64, 67, 72]);
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(67, 1.0, 0, false, 0);
+        chord_input.note_on(60, 1.0, 1, false, 0);
+        chord_input.note_on(64, 1.0, 2, false, 0);
+        assert_eq!(chord_of(&chord_input), [67, 60, 64]);
+
+        // a pressed again note moves to the end
+        chord_input.note_off(67, 3, false, 0);
+        chord_input.note_on(67, 1.0, 4, false, 0);
+        assert_eq!(chord_of(&chord_input), [60, 64, 67]);
+    }
+
+    #[test]
+    fn test_single_key() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 0);
+        assert!(chord_input.is_single_key());
+
+        // a chord that is released note by note ends on a note that was not pressed on its own
+        chord_input.note_on(64, 1.0, 1, false, 0);
+        chord_input.note_on(67, 1.0, 2, false, 0);
+        chord_input.note_off(67, 3, false, 0);
+        chord_input.note_off(64, 4, false, 0);
+        assert_eq!(chord_of(&chord_input), [60]);
+        assert!(!chord_input.is_single_key());
+
+        chord_input.note_off(60, 5, false, 0);
+        chord_input.note_on(62, 1.0, 6, false, 0);
+        assert!(chord_input.is_single_key());
+    }
+
+    #[test]
+    fn test_sustain() {
+        let mut chord_input = ChordInput::default();
+
+        chord_input.note_on(60, 1.0, 0, false, 0);
+        chord_input.note_on(64, 1.0, 0, false, 0);
+        chord_input.set_sustain(true, 10, false, 0);
+        chord_input.note_off(60, 20, false, 0);
+        chord_input.note_off(64, 20, false, 0);
+        assert_eq!(chord_of(&chord_input), [60, 64]);
+
+        // notes played with the pedal down add up, like on a piano
+        chord_input.note_on(67, 1.0, 30, false, 0);
+        assert_eq!(chord_of(&chord_input), [60, 64, 67]);
+
+        chord_input.set_sustain(false, 40, false, 0);
+        assert_eq!(chord_of(&chord_input), [67]);
+
+        // the released notes are latched when the pedal is released
+        chord_input.set_sustain(true, 50, true, 0);
+        chord_input.note_off(67, 60, true, 0);
+        chord_input.set_sustain(false, 70, true, 0);
+        assert_eq!(chord_of(&chord_input), [67]);
+        assert!(chord_input.is_latched());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::chord::Chord;
+
+/// A chord shape of the chord memory, one bit per interval above the lowest note of the chord it was learned from. It
+/// is stored in the plugin state as this number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChordShape(pub u128);
+
+impl ChordShape {
+    /// The major triad, until another shape is learned.
+    pub const MAJOR: ChordShape = ChordShape(1 | (1 << 4) | (1 << 7));
+
+    pub fn learn(chord: &Chord) -> Self {
+        let lowest = chord.lowest().unwrap_or_default();
+        ChordShape(chord.iter().fold(0, |shape, note| shape | (1 << (note - lowest))))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Writes the shape on top of the root, all notes with the same velocity. Notes above the MIDI range are left out.
+    pub fn transpose(&self, root: u8, velocity: Option<f32>, chord: &mut Chord) {
+        chord.clear();
+        for interval in (0..128u8).filter(|interval| self.0 & (1 << interval) != 0) {
+            if let Some(note) = root.checked_add(interval).filter(|note| *note < 128) {
+                chord.insert_with_velocity(note, velocity);
+            }
+        }
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::chord_memory::ChordShape;
+
+    fn transposed(shape: ChordShape, root: u8) -> Vec<u8> {
+        let mut chord = Chord::default();
+        shape.transpose(root, None, &mut chord);
+        chord.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_learn_and_transpose() {
+        assert_eq!(transposed(ChordShape::MAJOR, 60), [60, 64, 67]);
+
+        // a minor seventh chord, learned in any order
+        let shape = ChordShape::learn(&Chord::from([64, 57, 67, 60]));
+        assert_eq!(transposed(shape, 62), [62, 65, 69, 72]);
+        assert_eq!(transposed(shape, 120), [120, 123, 127]);
+
+        let wide = ChordShape::learn(&Chord::from([0, 127]));
+        assert_eq!(transposed(wide, 0), [0, 127]);
+
+        assert!(ChordShape::learn(&Chord::default()).is_empty());
+    }
+}
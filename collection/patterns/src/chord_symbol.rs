@@ -0,0 +1,171 @@
+use nih_plug::midi::PluginNoteEvent;
+use nih_plug::prelude::*;
+use crate::chord::Chord;
+use crate::utils::ChordSymbolMode;
+
+/// A chord quality of the chord dictionary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChordQuality {
+    pub name: &'static str,
+    /// the intervals above the root, starting with the root itself
+    pub intervals: &'static [u8],
+}
+
+/// The chord qualities a chord symbol can select. Quality number 1 is the first entry, 0 selects no chord.
+pub static CHORD_DICTIONARY: [ChordQuality; 21] = [
+    ChordQuality { name: "maj", intervals: &[0, 4, 7] },
+    ChordQuality { name: "min", intervals: &[0, 3, 7] },
+    ChordQuality { name: "dim", intervals: &[0, 3, 6] },
+    ChordQuality { name: "aug", intervals: &[0, 4, 8] },
+    ChordQuality { name: "sus2", intervals: &[0, 2, 7] },
+    ChordQuality { name: "sus4", intervals: &[0, 5, 7] },
+    ChordQuality { name: "7", intervals: &[0, 4, 7, 10] },
+    ChordQuality { name: "maj7", intervals: &[0, 4, 7, 11] },
+    ChordQuality { name: "m7", intervals: &[0, 3, 7, 10] },
+    ChordQuality { name: "m7b5", intervals: &[0, 3, 6, 10] },
+    ChordQuality { name: "dim7", intervals: &[0, 3, 6, 9] },
+    ChordQuality { name: "mMaj7", intervals: &[0, 3, 7, 11] },
+    ChordQuality { name: "7sus4", intervals: &[0, 5, 7, 10] },
+    ChordQuality { name: "6", intervals: &[0, 4, 7, 9] },
+    ChordQuality { name: "m6", intervals: &[0, 3, 7, 9] },
+    ChordQuality { name: "add9", intervals: &[0, 4, 7, 14] },
+    ChordQuality { name: "madd9", intervals: &[0, 3, 7, 14] },
+    ChordQuality { name: "9", intervals: &[0, 4, 7, 10, 14] },
+    ChordQuality { name: "maj9", intervals: &[0, 4, 7, 11, 14] },
+    ChordQuality { name: "m9", intervals: &[0, 3, 7, 10, 14] },
+    ChordQuality { name: "5", intervals: &[0, 7] },
+];
+
+/// The root and quality a chord channel last received as chord symbol messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChordSymbol {
+    pub root: Option<u8>,
+    /// the index into [CHORD_DICTIONARY]
+    pub quality: Option<usize>,
+}
+
+impl ChordSymbol {
+    /// Writes the notes of the chord from the root upwards, none until both root and quality are known.
+    pub fn expand(&self, chord: &mut Chord) {
+        chord.clear();
+        if let (Some(root), Some(quality)) = (self.root, self.quality.and_then(|idx| CHORD_DICTIONARY.get(idx))) {
+            for interval in quality.intervals {
+                if let Some(note) = root.checked_add(*interval).filter(|note| *note < 128) {
+                    chord.insert(note);
+                }
+            }
+        }
+    }
+}
+
+/// The messages that carry chord symbols: the root note as the value of a CC, the quality number as the value of a
+/// second CC or as a program change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChordSymbolInput {
+    pub mode: ChordSymbolMode,
+    pub root_cc: u8,
+    pub quality_cc: u8,
+}
+
+impl Default for ChordSymbolInput {
+    fn default() -> Self {
+        Self {
+            mode: ChordSymbolMode::Off,
+            root_cc: 20,
+            quality_cc: 21,
+        }
+    }
+}
+
+impl ChordSymbolInput {
+    /// Applies a chord symbol message to the symbol. Returns false for every other event.
+    pub fn process<P: Plugin>(&self, event: &PluginNoteEvent<P>, symbol: &mut ChordSymbol) -> bool {
+        match (self.mode, event) {
+            (ChordSymbolMode::Off, _) => false,
+            (_, PluginNoteEvent::<P>::MidiCC { cc, value, .. }) if *cc == self.root_cc => {
+                symbol.root = Some(midi_value(*value));
+                true
+            }
+            (ChordSymbolMode::ControlChange, PluginNoteEvent::<P>::MidiCC { cc, value, .. }) if *cc == self.quality_cc => {
+                symbol.quality = quality_of(midi_value(*value));
+                true
+            }
+            (ChordSymbolMode::ProgramChange, PluginNoteEvent::<P>::MidiProgramChange { program, .. }) => {
+                symbol.quality = quality_of(*program);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_symbol_event<P: Plugin>(&self, event: &PluginNoteEvent<P>) -> bool {
+        self.process::<P>(event, &mut ChordSymbol::default())
+    }
+}
+
+fn midi_value(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 127.0).round() as u8
+}
+
+fn quality_of(number: u8) -> Option<usize> {
+    (number as usize).checked_sub(1).filter(|idx| *idx < CHORD_DICTIONARY.len())
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::midi::PluginNoteEvent;
+    use crate::Patterns;
+    use crate::chord::Chord;
+    use crate::chord_symbol::{ChordSymbol, ChordSymbolInput, CHORD_DICTIONARY};
+    use crate::utils::ChordSymbolMode;
+
+    fn cc(cc: u8, value: u8) -> PluginNoteEvent<Patterns> {
+        PluginNoteEvent::<Patterns>::MidiCC { timing: 0, channel: 15, cc, value: value as f32 / 127.0 }
+    }
+
+    fn expanded(symbol: &ChordSymbol) -> Vec<u8> {
+        let mut chord = Chord::default();
+        symbol.expand(&mut chord);
+        chord.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expanded(&ChordSymbol { root: Some(60), quality: Some(0) }), [60, 64, 67]);
+        assert_eq!(expanded(&ChordSymbol { root: Some(57), quality: Some(8) }), [57, 60, 64, 67]);
+        assert_eq!(expanded(&ChordSymbol { root: Some(120), quality: Some(17) }), [120, 124, 127]);
+        assert!(expanded(&ChordSymbol { root: Some(60), quality: None }).is_empty());
+        assert!(expanded(&ChordSymbol { root: None, quality: Some(0) }).is_empty());
+        assert!(CHORD_DICTIONARY.iter().all(|quality| quality.intervals[0] == 0));
+    }
+
+    #[test]
+    fn test_process() {
+        let mut symbol = ChordSymbol::default();
+
+        let input = ChordSymbolInput::default();
+        assert!(!input.process::<Patterns>(&cc(20, 60), &mut symbol));
+
+        let input = ChordSymbolInput { mode: ChordSymbolMode::ControlChange, ..Default::default() };
+        assert!(input.process::<Patterns>(&cc(20, 60), &mut symbol));
+        assert!(input.process::<Patterns>(&cc(21, 2), &mut symbol));
+        assert!(!input.process::<Patterns>(&cc(22, 2), &mut symbol));
+        assert_eq!(symbol, ChordSymbol { root: Some(60), quality: Some(1) });
+
+        // quality 0 and numbers beyond the dictionary select no chord
+        assert!(input.process::<Patterns>(&cc(21, 0), &mut symbol));
+        assert_eq!(symbol.quality, None);
+        assert!(input.process::<Patterns>(&cc(21, 127), &mut symbol));
+        assert_eq!(symbol.quality, None);
+
+        let input = ChordSymbolInput { mode: ChordSymbolMode::ProgramChange, ..Default::default() };
+        let program_change = PluginNoteEvent::<Patterns>::MidiProgramChange { timing: 0, channel: 15, program: 9 };
+        assert!(input.process::<Patterns>(&program_change, &mut symbol));
+        assert!(!input.is_symbol_event::<Patterns>(&cc(21, 2)));
+        assert_eq!(symbol, ChordSymbol { root: Some(60), quality: Some(8) });
+    }
+}
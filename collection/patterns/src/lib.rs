@@ -1,17 +1,48 @@
 mod active_note;
+mod chord;
+mod chord_analysis;
+mod chord_input;
+mod chord_memory;
+mod chord_symbol;
 mod processors;
+mod scale;
+mod strum;
 mod utils;
+mod voicing;
 
-use crate::processors::ChordPatternProcessor;
+use crate::active_note::NUM_KEYS;
+use crate::processors::{ChordPatternProcessor, PatternSettings, EVENT_CAPACITY, MAX_CHORD_SLOTS, NUM_CHANNELS,
+                        NUM_VELOCITY_OFFSETS, SEND_EVENT_CAPACITY};
 use nih_plug::prelude::*;
-use std::cmp::max;
-use std::sync::{Arc};
+use modular_host::{is_panic, HeadlessPlugin, HostContext, NihContext};
+use std::sync::{Arc, RwLock};
 use nih_plug::midi::NoteEvent;
-use crate::utils::{get_note_of_event, set_note_of_event, get_chord_data, KeyboardMode};
+use crate::chord_memory::ChordShape;
+use crate::chord_symbol::ChordSymbolInput;
+use crate::strum::StrumSettings;
+use crate::utils::{time_to_samples, ChordChangeMode, ChordOrder, ChordSymbolMode, KeyboardMode, NoteRange, OutOfRange, PatternMode, ScaleKey, ScaleType, StrumDirection, TimeUnit, VoicingDrop};
+use crate::voicing::Voicing;
 
 pub struct Patterns {
     params: Arc<PatternsParams>,
     processor: ChordPatternProcessor<Patterns>,
+
+    // preallocated buffers, so that process() does not allocate
+    note_events: Vec<PluginNoteEvent<Patterns>>,
+    other_events: Vec<PluginNoteEvent<Patterns>>,
+
+    // samples processed so far and the transport of the current block
+    sample_count: u64,
+    /// the position of the transport while it is playing, otherwise the samples processed so far
+    position: u64,
+    tempo: f64,
+    sample_rate: f32,
+    playing: bool,
+
+    /// NoteOffs of a reset that are sent at the start of the next block
+    pending_note_offs: Vec<PluginNoteEvent<Patterns>>,
+    /// the chord channels, keyboard mode and octave range the held pattern keys were pressed with
+    key_params: Option<(i32, i32, KeyboardMode, i32)>,
 }
 
 #[derive(Params)]
@@ -19,6 +50,28 @@ struct PatternsParams {
     #[id = "chord_channel"]
     chord_channel: IntParam,
 
+    #[id = "chord_channels"]
+    chord_channels: IntParam,
+
+    #[id = "chord_symbol_mode"]
+    chord_symbol_mode: EnumParam<ChordSymbolMode>,
+
+    #[id = "chord_root_cc"]
+    chord_root_cc: IntParam,
+
+    #[id = "chord_quality_cc"]
+    chord_quality_cc: IntParam,
+
+    #[id = "chord_memory"]
+    chord_memory: BoolParam,
+
+    #[id = "chord_memory_learn"]
+    chord_memory_learn: BoolParam,
+
+    /// the chord shape of the chord memory, see [ChordShape]
+    #[persist = "chord_shape"]
+    chord_shape: RwLock<u128>,
+
     #[id = "wrap_threshold"]
     wrap_threshold: IntParam,
 
@@ -30,12 +83,120 @@ struct PatternsParams {
 
     #[id = "key_mode"]
     key_mode: EnumParam<KeyboardMode>,
+
+    #[id = "root_note"]
+    root_note: IntParam,
+
+    #[id = "chord_change_mode"]
+    chord_change_mode: EnumParam<ChordChangeMode>,
+
+    #[id = "latch"]
+    latch: BoolParam,
+
+    #[id = "bridge_time"]
+    bridge_time: FloatParam,
+
+    #[id = "bridge_beats"]
+    bridge_beats: FloatParam,
+
+    #[id = "bridge_time_unit"]
+    bridge_time_unit: EnumParam<TimeUnit>,
+
+    #[id = "chord_order"]
+    chord_order: EnumParam<ChordOrder>,
+
+    #[id = "pattern_mode"]
+    pattern_mode: EnumParam<PatternMode>,
+
+    #[id = "scale"]
+    scale: EnumParam<ScaleType>,
+
+    #[id = "scale_key"]
+    scale_key: EnumParam<ScaleKey>,
+
+    #[id = "inversion"]
+    inversion: IntParam,
+
+    #[id = "drop"]
+    drop: EnumParam<VoicingDrop>,
+
+    #[id = "spread"]
+    spread: BoolParam,
+
+    #[id = "bass_octave"]
+    bass_octave: BoolParam,
+
+    #[id = "window_low"]
+    window_low: IntParam,
+
+    #[id = "window_high"]
+    window_high: IntParam,
+
+    #[id = "voice_leading"]
+    voice_leading: BoolParam,
+
+    #[id = "out_of_range"]
+    out_of_range: EnumParam<OutOfRange>,
+
+    #[id = "note_low"]
+    note_low: IntParam,
+
+    #[id = "note_high"]
+    note_high: IntParam,
+
+    #[id = "chord_velocity"]
+    chord_velocity: FloatParam,
+
+    #[id = "release_velocity"]
+    release_velocity: BoolParam,
+
+    #[id = "strum_time"]
+    strum_time: FloatParam,
+
+    #[id = "strum_beats"]
+    strum_beats: FloatParam,
+
+    #[id = "strum_time_unit"]
+    strum_time_unit: EnumParam<TimeUnit>,
+
+    #[id = "strum_direction"]
+    strum_direction: EnumParam<StrumDirection>,
+
+    #[id = "strum_velocity_ramp"]
+    strum_velocity_ramp: FloatParam,
+
+    #[nested(array, group = "Chord Slots")]
+    channel_params: [ChannelParams; NUM_CHANNELS],
+
+    #[nested(array, group = "Index Velocity")]
+    index_params: [IndexParams; NUM_VELOCITY_OFFSETS],
+}
+
+#[derive(Params)]
+struct ChannelParams {
+    #[id = "chord_slot_channel_"]
+    chord_slot: IntParam,
+}
+
+#[derive(Params)]
+struct IndexParams {
+    /// in MIDI velocity steps
+    #[id = "velocity_offset_index_"]
+    velocity_offset: IntParam,
 }
 
 impl Default for PatternsParams {
     fn default() -> Self {
         Self {
             chord_channel: IntParam::new("Chord Channel", 16, IntRange::Linear { min: 1, max: 16 }),
+            chord_channels: IntParam::new("Chord Channels", 1,
+                                          IntRange::Linear { min: 1, max: MAX_CHORD_SLOTS as i32 }),
+            chord_symbol_mode: EnumParam::new("Chord Symbol Input", ChordSymbolMode::Off),
+            chord_root_cc: IntParam::new("Chord Root CC", 20, IntRange::Linear { min: 0, max: 127 }),
+            chord_quality_cc: IntParam::new("Chord Quality CC", 21, IntRange::Linear { min: 0, max: 127 }),
+            chord_memory: BoolParam::new("Chord Memory", false),
+            chord_memory_learn: BoolParam::new("Learn Chord", false),
+            chord_shape: RwLock::new(ChordShape::MAJOR.0),
             wrap_threshold: IntParam::new(
                 "Wrap Threshold",
                 12,
@@ -44,6 +205,41 @@ impl Default for PatternsParams {
             auto_threshold: BoolParam::new("Auto Threshold", true),
             octave_range: IntParam::new("Octave Range", 12, IntRange::Linear { min: 1, max: 127 }),
             key_mode: EnumParam::new("Keyboard Mode", KeyboardMode::AllKeys),
+            root_note: IntParam::new("Root Note", 60, IntRange::Linear { min: 0, max: 127 }),
+            chord_change_mode: EnumParam::new("Chord Change", ChordChangeMode::Retrigger),
+            latch: BoolParam::new("Latch", false),
+            bridge_time: FloatParam::new("Bridge Time", 0.0, FloatRange::Linear { min: 0.0, max: 1000.0 }),
+            bridge_beats: FloatParam::new("Bridge Beats", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            bridge_time_unit: EnumParam::new("Bridge Time Unit", TimeUnit::Milliseconds),
+            chord_order: EnumParam::new("Chord Order", ChordOrder::Ascending),
+            pattern_mode: EnumParam::new("Pattern Mode", PatternMode::Chord),
+            scale: EnumParam::new("Scale", ScaleType::Chord),
+            scale_key: EnumParam::new("Scale Key", ScaleKey::C),
+            inversion: IntParam::new("Inversion", 0, IntRange::Linear { min: 0, max: 11 }),
+            drop: EnumParam::new("Drop", VoicingDrop::None),
+            spread: BoolParam::new("Spread", false),
+            bass_octave: BoolParam::new("Bass Octave", false),
+            window_low: IntParam::new("Window Low", 0, IntRange::Linear { min: 0, max: 127 }),
+            window_high: IntParam::new("Window High", 127, IntRange::Linear { min: 0, max: 127 }),
+            voice_leading: BoolParam::new("Voice Leading", false),
+            out_of_range: EnumParam::new("Out of Range", OutOfRange::Drop),
+            note_low: IntParam::new("Note Low", 0, IntRange::Linear { min: 0, max: 127 }),
+            note_high: IntParam::new("Note High", 127, IntRange::Linear { min: 0, max: 127 }),
+            chord_velocity: FloatParam::new("Chord Velocity", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            release_velocity: BoolParam::new("Release Velocity", false),
+            strum_time: FloatParam::new("Strum Time", 0.0, FloatRange::Linear { min: 0.0, max: 1000.0 }),
+            strum_beats: FloatParam::new("Strum Beats", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            strum_time_unit: EnumParam::new("Strum Time Unit", TimeUnit::Milliseconds),
+            strum_direction: EnumParam::new("Strum Direction", StrumDirection::Up),
+            strum_velocity_ramp: FloatParam::new("Strum Velocity Ramp", 0.0, FloatRange::Linear { min: 0.0, max: 1.0 }),
+            channel_params: std::array::from_fn(|idx| ChannelParams {
+                chord_slot: IntParam::new(format!("Channel {} Chord Slot", idx + 1), 1,
+                                          IntRange::Linear { min: 1, max: MAX_CHORD_SLOTS as i32 }),
+            }),
+            index_params: std::array::from_fn(|idx| IndexParams {
+                velocity_offset: IntParam::new(format!("Index {} Velocity Offset", idx), 0,
+                                               IntRange::Linear { min: -127, max: 127 }),
+            }),
         }
     }
 }
@@ -57,40 +253,162 @@ impl Default for Patterns{
         Self {
             params: Arc::new(PatternsParams::default()),
             processor: ChordPatternProcessor::default(),
+            note_events: Vec::with_capacity(SEND_EVENT_CAPACITY),
+            other_events: Vec::with_capacity(EVENT_CAPACITY),
+            sample_count: 0,
+            position: 0,
+            tempo: 120.0,
+            sample_rate: 44_100.0,
+            playing: false,
+            // one note off for every key that can be held on any channel
+            pending_note_offs: Vec::with_capacity(NUM_CHANNELS * NUM_KEYS),
+            key_params: None,
         }
     }
 }
 
 impl Patterns {
-    fn get_note_event_channel(&self, note_event: &PluginNoteEvent<Patterns>) -> u8 {
-        match note_event {
-            PluginNoteEvent::<Patterns>::NoteOn { channel, .. }
-            | PluginNoteEvent::<Patterns>::NoteOff { channel, .. }
-            | PluginNoteEvent::<Patterns>::Choke { channel, .. }
-            | PluginNoteEvent::<Patterns>::VoiceTerminated { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyPressure { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyVolume { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyPan { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyTuning { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyVibrato { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyExpression { channel, .. }
-            | PluginNoteEvent::<Patterns>::PolyBrightness { channel, .. }
-            | PluginNoteEvent::<Patterns>::MidiChannelPressure { channel, .. }
-            | PluginNoteEvent::<Patterns>::MidiPitchBend { channel, .. }
-            | PluginNoteEvent::<Patterns>::MidiCC { channel, .. }
-            | PluginNoteEvent::<Patterns>::MidiProgramChange { channel, .. } => *channel,
-            _ => 0,
-        }
-    }
-
-    fn get_threshold(&self) -> u8 {
-        if self.params.auto_threshold.value() {
-            max(self.processor.chord.len() as u8, 1) // minimum wrap threshold of 1 to not divide by zero
-        }
-        else {
-            self.params.wrap_threshold.value() as u8
+    /// Applies the pattern and chord changes of one timing group and sends the resulting note events followed by the
+    /// non-note events of the group. Polyphonic events are mapped to the notes their pattern keys trigger right now.
+    fn send_cycle_events(&mut self, context: &mut impl HostContext<Self>, timing: u32) {
+        let mut settings = self.get_pattern_settings();
+        settings.strum.beat = self.beat_at(timing);
+
+        self.note_events.clear();
+        self.processor.end_cycle(&mut self.note_events, timing, &settings);
+
+        for e in self.note_events.iter() {
+            context.send_event(*e);
         }
+
+        for event in self.other_events.iter() {
+            if let Some(modulated_event) = self.processor.modulate_event(event) {
+                context.send_event(modulated_event);
+            }
+        }
+        self.other_events.clear();
+    }
+
+    fn get_pattern_settings(&self) -> PatternSettings {
+        PatternSettings {
+            chord_channel: (self.params.chord_channel.value() - 1) as u8,
+            chord_channels: self.params.chord_channels.value() as u8,
+            channel_slots: std::array::from_fn(|channel| {
+                (self.params.channel_params[channel].chord_slot.value() - 1) as u8
+            }),
+            wrap_threshold: self.params.wrap_threshold.value() as u8,
+            auto_threshold: self.params.auto_threshold.value(),
+            chord_symbol: ChordSymbolInput {
+                mode: self.params.chord_symbol_mode.value(),
+                root_cc: self.params.chord_root_cc.value() as u8,
+                quality_cc: self.params.chord_quality_cc.value() as u8,
+            },
+            chord_memory: self.get_chord_memory(),
+            octave_range: self.params.octave_range.value() as u8,
+            keyboard_mode: self.params.key_mode.value(),
+            root_note: self.params.root_note.value() as u8,
+            chord_change_mode: self.params.chord_change_mode.value(),
+            latch: self.params.latch.value(),
+            bridge_samples: self.time_to_samples(&self.params.bridge_time, &self.params.bridge_beats,
+                                                 self.params.bridge_time_unit.value()),
+            chord_order: self.params.chord_order.value(),
+            pattern_mode: self.params.pattern_mode.value(),
+            scale_type: self.params.scale.value(),
+            scale_key: self.params.scale_key.value(),
+            voicing: self.get_voicing(),
+            note_range: NoteRange {
+                low: self.params.note_low.value() as u8,
+                high: self.params.note_high.value() as u8,
+                out_of_range: self.params.out_of_range.value(),
+            },
+            chord_velocity: self.params.chord_velocity.value(),
+            release_velocity: self.params.release_velocity.value(),
+            velocity_offsets: std::array::from_fn(|idx| {
+                self.params.index_params[idx].velocity_offset.value() as f32 / 127.0
+            }),
+            strum: StrumSettings {
+                samples: self.time_to_samples(&self.params.strum_time, &self.params.strum_beats,
+                                              self.params.strum_time_unit.value()),
+                direction: self.params.strum_direction.value(),
+                velocity_ramp: self.params.strum_velocity_ramp.value(),
+                beat: 0,
+            },
+        }
+    }
+
+    /// The length in samples of a time that has a parameter for each unit.
+    fn time_to_samples(&self, milliseconds: &FloatParam, beats: &FloatParam, time_unit: TimeUnit) -> u64 {
+        let time = match time_unit {
+            TimeUnit::Milliseconds => milliseconds.value(),
+            TimeUnit::Beats => beats.value(),
+        };
+        time_to_samples(time, time_unit, self.tempo, self.sample_rate)
     }
+
+    /// The beat a timing of the current block is on.
+    fn beat_at(&self, timing: u32) -> u64 {
+        let samples_per_beat = 60.0 / self.tempo.max(0.00001) * self.sample_rate as f64;
+        ((self.position + timing as u64) as f64 / samples_per_beat).floor() as u64
+    }
+
+    /// The stored chord shape while the chord memory is on. Learning turns it off, so the held chord is learned as it
+    /// is.
+    fn get_chord_memory(&self) -> Option<ChordShape> {
+        if !self.params.chord_memory.value() || self.params.chord_memory_learn.value() {
+            return None;
+        }
+        // the lock is only held briefly and never waited for on the audio thread
+        self.params.chord_shape.try_read().ok().map(|shape| ChordShape(*shape)).filter(|shape| !shape.is_empty())
+    }
+
+    /// Stores the shape of the first held chord while learning is on.
+    fn learn_chord_shape(&self) {
+        if !self.params.chord_memory_learn.value() {
+            return;
+        }
+        if let Some(slot) = self.processor.slots.iter().find(|slot| slot.chord.len() > 1) {
+            if let Ok(mut shape) = self.params.chord_shape.try_write() {
+                *shape = ChordShape::learn(&slot.chord).0;
+            }
+        }
+    }
+
+    fn get_voicing(&self) -> Voicing {
+        Voicing {
+            inversion: self.params.inversion.value() as u8,
+            drop: self.params.drop.value(),
+            spread: self.params.spread.value(),
+            bass_octave: self.params.bass_octave.value(),
+            window_low: self.params.window_low.value() as u8,
+            window_high: self.params.window_high.value() as u8,
+            voice_leading: self.params.voice_leading.value(),
+        }
+    }
+
+    /// Applies the chord changes of bridges that end before the given timing and starts the notes of strums that are
+    /// due before it, in the order of their timing.
+    fn send_scheduled_events(&mut self, context: &mut impl HostContext<Self>, until: u32) {
+        loop {
+            let chord_change = self.processor.next_chord_change().filter(|timing| *timing < until);
+            let strum_note = self.processor.next_strum_note().filter(|timing| *timing < until);
+            match (chord_change, strum_note) {
+                (Some(chord_change), strum_note) if strum_note.is_none_or(|strum_note| strum_note > chord_change) => {
+                    let settings = self.get_pattern_settings();
+                    self.processor.expire_chord_notes(chord_change, &settings);
+                    self.send_cycle_events(context, chord_change);
+                }
+                (_, Some(strum_note)) => {
+                    self.note_events.clear();
+                    self.processor.send_strum_notes(&mut self.note_events, strum_note);
+                    for e in self.note_events.iter() {
+                        context.send_event(*e);
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
 }
 
 impl Plugin for Patterns {
@@ -128,71 +446,127 @@ impl Plugin for Patterns {
         self.params.clone()
     }
 
+    fn reset(&mut self) {
+        // the host cannot receive events here, the notes that are still sounding end in the next block
+        self.processor.release_pattern_keys(&mut self.pending_note_offs, 0);
+        self.processor.reset();
+        self.sample_count = 0;
+        self.playing = false;
+        self.key_params = None;
+    }
+
     fn process(
         &mut self,
-        _buffer: &mut Buffer,
+        buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
         context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
-        {
-            let mut next_event = context.next_event();
-            let mut sample_id = 999;
+        self.process_events(buffer.samples() as u32, &mut NihContext::new(context))
+    }
+}
 
-            let mut other_events: Vec<PluginNoteEvent<Patterns>> = vec![];
+impl HeadlessPlugin for Patterns {
+    fn process_events(&mut self, num_samples: u32, context: &mut impl HostContext<Self>) -> ProcessStatus {
+        let transport = context.transport();
+        self.tempo = transport.tempo.unwrap_or(120.0);
+        self.sample_rate = transport.sample_rate;
+        self.position = match transport.pos_samples {
+            Some(pos_samples) if transport.playing => pos_samples.max(0) as u64,
+            _ => self.sample_count,
+        };
+        self.processor.start_block(self.sample_count);
 
-            while let Some(event) = next_event {
-                if event.timing() != sample_id {
-                    let note_events = &mut vec![];
-                    self.processor.end_cycle(note_events, sample_id, self.get_threshold(), self.params.octave_range.value() as u8, self.params.key_mode.value());
+        // the notes of held pattern keys end when the transport stops or when a parameter changes what the keys
+        // play, instead of hanging until the keys are released
+        let key_params = (self.params.chord_channel.value(), self.params.chord_channels.value(),
+                          self.params.key_mode.value(), self.params.octave_range.value());
+        let stopped = self.playing && !transport.playing;
+        if stopped || self.key_params.is_some_and(|previous| previous != key_params) {
+            self.processor.release_pattern_keys(&mut self.pending_note_offs, 0);
+        }
+        self.playing = transport.playing;
+        self.key_params = Some(key_params);
 
-                    for e in note_events {
-                        context.send_event(*e);
-                    }
-                    // TODO: Modulate other events too
-                    for event in other_events.iter() {
-                        context.send_event(*event);
-                    }
-                    other_events.clear();
-                    sample_id = event.timing();
-                }
+        for event in self.pending_note_offs.drain(..) {
+            context.send_event(event);
+        }
 
-                let note_channel = utils::get_channel_of_event::<Patterns>(&event);
+        if !self.params.latch.value() && self.processor.is_chord_latched() {
+            let settings = self.get_pattern_settings();
+            self.processor.unlatch_chord(&settings);
+            self.send_cycle_events(context, 0);
+        }
 
-                if note_channel == Some((self.params.chord_channel.value() - 1) as u8) {
-                    self.processor.process_chord_event(event);
-                } else {
-                    match event {
-                        PluginNoteEvent::<Patterns>::NoteOn { .. } | PluginNoteEvent::<Patterns>::NoteOff { .. } => {
-                            self.processor.process_pattern_event(event)
-                        }
-                        _ => other_events.push(event),
-                    }
-                }
+        let mut next_event = context.next_event();
+        // changes that are pending from the last block, e.g. of parameters, are applied at the start of the block
+        let mut sample_id = 0;
 
-                next_event = context.next_event();
+        while let Some(event) = next_event {
+            if event.timing() != sample_id {
+                self.send_cycle_events(context, sample_id);
+                self.send_scheduled_events(context, event.timing());
+                sample_id = event.timing();
             }
-            // process last chord change. In the above loop the last chord change will not be processed otherwise because the sample_id
-            // does not change after the last note.
 
-            let note_events = &mut vec![];
-            self.processor.end_cycle(note_events, sample_id, self.get_threshold(), self.params.octave_range.value() as u8, self.params.key_mode.value());
+            let settings = self.get_pattern_settings();
+            let panic = is_panic(&event);
+            let sustain = utils::get_sustain_of_event::<Patterns>(&event);
+            // panic messages, the sustain pedal and chord symbols have no note, only a channel
+            let channel = utils::get_channel_of_message::<Patterns>(&event).unwrap_or_default();
+            let is_chord_channel = settings.chord_slot(channel).is_some()
+                && (utils::get_channel_of_event::<Patterns>(&event).is_some() || panic || sustain.is_some()
+                    || settings.chord_symbol.is_symbol_event::<Patterns>(&event));
 
-            for e in note_events {
-                context.send_event(*e);
-            }
-            // TODO: Modulate other events too
-            for note_event in other_events.iter() {
-                if let Some(raw_note) = get_note_of_event::<Patterns>(&note_event) {
-                    let chord_data = get_chord_data(&self.processor.chord.iter().cloned().collect(), raw_note, self.get_threshold(), self.params.octave_range.value() as u8);
-                    if let Some(triggered_note) = chord_data.triggered_note {
-                        context.send_event(set_note_of_event::<Patterns>(note_event, triggered_note));
+            if panic || sustain.is_some() {
+                // the events before the message are applied first, so their notes end as well
+                self.send_cycle_events(context, sample_id);
+                self.note_events.clear();
+                match sustain {
+                    Some(sustain) if is_chord_channel => {
+                        self.processor.sustain_chord(channel, sustain, event.timing(), &settings);
+                    }
+                    Some(sustain) => {
+                        self.processor.sustain_pattern_keys(channel, sustain, &mut self.note_events, event.timing());
+                    }
+                    None => {
+                        self.processor.release_pattern_keys(&mut self.note_events, event.timing());
+                        if is_chord_channel {
+                            self.processor.release_chord(channel, &settings);
+                        }
                     }
                 }
+                for e in self.note_events.iter() {
+                    context.send_event(*e);
+                }
             }
 
-            other_events.clear();
+            if is_chord_channel {
+                self.processor.process_chord_event(event, &settings);
+            } else {
+                match event {
+                    PluginNoteEvent::<Patterns>::NoteOn { .. } | PluginNoteEvent::<Patterns>::NoteOff { .. } => {
+                        self.processor.process_pattern_event(event)
+                    }
+                    // the plugin holds the notes of the pattern keys itself, the pedal is not passed on
+                    _ if sustain.is_some() => {}
+                    _ => {
+                        // never grow the buffer on the audio thread
+                        if self.other_events.len() < self.other_events.capacity() {
+                            self.other_events.push(event)
+                        }
+                    }
+                }
+            }
 
+            next_event = context.next_event();
         }
+        // process last chord change. In the above loop the last chord change will not be processed otherwise because the sample_id
+        // does not change after the last note.
+        self.send_cycle_events(context, sample_id);
+        self.send_scheduled_events(context, num_samples);
+        self.learn_chord_shape();
+
+        self.sample_count += num_samples as u64;
 
         ProcessStatus::Normal
     }
@@ -215,13 +589,441 @@ impl Vst3Plugin for Patterns {
 nih_export_clap!(Patterns);
 nih_export_vst3!(Patterns);
 
-/*
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
 #[cfg(test)]
 mod tests {
+    use nih_plug::prelude::*;
+    use modular_host::{set_parameter, TestHost};
+    use crate::Patterns;
+    use crate::active_note::NUM_KEYS;
+    use crate::processors::SEND_EVENT_CAPACITY;
+
+    fn note_on(timing: u32, channel: u8, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn { timing, voice_id: None, channel, note, velocity: 1.0 }
+    }
+
+    fn note_off(timing: u32, channel: u8, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOff { timing, voice_id: None, channel, note, velocity: 1.0 }
+    }
+
+    fn start(block_size: u32) -> (TestHost<Patterns>, Patterns) {
+        let mut host = TestHost::new(block_size);
+        let mut plugin = Patterns::default();
+        assert!(host.initialize(&mut plugin));
+        (host, plugin)
+    }
+
+    #[test]
+    fn test_process() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 61)); // chord index 2
+        host.queue_event(note_off(300, 0, 61));
+        host.run(&mut plugin, 512);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 64), note_off(300, 0, 64)]);
+    }
+
+    #[test]
+    fn test_process_groups_events_of_the_same_timing() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        // the pattern key arrives before the chord change of the same timing, but plays the new chord
+        host.queue_event(note_on(100, 0, 60));
+        for note in [60, 64, 67] {
+            host.queue_event(note_off(100, 15, note));
+        }
+        for note in [62, 65, 69] {
+            host.queue_event(note_on(100, 15, note));
+        }
+        host.run(&mut plugin, 512);
+
+        assert_eq!(host.output_events(), [note_on(100, 0, 62)]);
+    }
+
+    #[test]
+    fn test_process_changes_between_blocks() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(0, 0, 60));
+        host.run(&mut plugin, 64);
+        assert_eq!(host.take_output_events(), [note_on(0, 0, 60)]);
+
+        // the parameter change applies at the start of the next block, even without events in it
+        assert!(set_parameter(&plugin, "chord_order", 1.0)); // descending
+        host.run(&mut plugin, 128);
+        assert_eq!(host.take_output_events(), [note_off(64, 0, 60), note_on(64, 0, 67)]);
+
+        // and in a block with events
+        assert!(set_parameter(&plugin, "chord_order", 0.0)); // ascending
+        host.queue_event(note_on(200, 0, 63)); // chord index 1, an octave up
+        host.run(&mut plugin, 128);
+        assert_eq!(host.take_output_events(), [note_off(192, 0, 67), note_on(192, 0, 60), note_on(200, 0, 72)]);
+    }
+
+    #[test]
+    fn test_process_repeated_note_on() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.queue_event(note_on(20, 0, 60));
+        host.queue_event(note_off(30, 0, 60));
+        // pressed and released at the same time
+        host.queue_event(note_on(40, 0, 62));
+        host.queue_event(note_off(40, 0, 62));
+        host.run(&mut plugin, 64);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(20, 0, 60), note_on(20, 0, 60), note_off(30, 0, 60)]);
+    }
+
+    #[test]
+    fn test_process_parameter_change_releases_keys() {
+        for (id, value) in [("chord_channel", 1.0), ("key_mode", 1.0), ("octave_range", 24.0)] {
+            let (mut host, mut plugin) = start(64);
+
+            for note in [60, 64, 67] {
+                host.queue_event(note_on(0, 15, note));
+            }
+            host.queue_event(note_on(10, 0, 60));
+            host.run(&mut plugin, 64);
+
+            // the note ends with the change, the release of the key does not send anything
+            assert!(set_parameter(&plugin, id, value));
+            host.queue_event(note_off(100, 0, 60));
+            host.run(&mut plugin, 64);
+
+            assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(64, 0, 60)], "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_process_panic() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.queue_event(note_on(100, 0, 62));
+        // the key pressed at the same time ends as well, the message is passed on
+        let all_notes_off = NoteEvent::MidiCC { timing: 100, channel: 0, cc: 123, value: 0.0 };
+        host.queue_event(all_notes_off);
+        host.queue_event(note_off(200, 0, 60));
+        host.run(&mut plugin, 256);
+
+        assert_eq!(host.take_output_events(), [
+            note_on(10, 0, 60),
+            note_on(100, 0, 67),
+            note_off(100, 0, 60),
+            note_off(100, 0, 67),
+            all_notes_off,
+        ]);
+
+        // a panic message of the chord channel releases the chord as well
+        host.queue_event(NoteEvent::MidiCC { timing: 300, channel: 15, cc: 120, value: 0.0 });
+        host.queue_event(note_on(310, 0, 60));
+        host.run(&mut plugin, 64);
+        assert!(host.output_events().is_empty());
+    }
+
+    #[test]
+    fn test_process_sustain_pattern_keys() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.queue_event(NoteEvent::MidiCC { timing: 20, channel: 0, cc: 64, value: 1.0 });
+        host.queue_event(note_off(30, 0, 60));
+        // the sustained note follows the chord change
+        for note in [60, 64, 67] {
+            host.queue_event(note_off(100, 15, note));
+        }
+        for note in [65, 69, 72] {
+            host.queue_event(note_on(100, 15, note));
+        }
+        host.queue_event(NoteEvent::MidiCC { timing: 200, channel: 0, cc: 64, value: 0.0 });
+        host.run(&mut plugin, 256);
+
+        assert_eq!(host.output_events(), [
+            note_on(10, 0, 60),
+            note_off(100, 0, 60),
+            note_on(100, 0, 65),
+            note_off(200, 0, 65),
+        ]);
+    }
+
+    #[test]
+    fn test_process_sustain_chord() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(NoteEvent::MidiCC { timing: 5, channel: 15, cc: 64, value: 1.0 });
+        for note in [60, 64, 67] {
+            host.queue_event(note_off(50, 15, note));
+        }
+        host.queue_event(note_on(100, 0, 60));
+        host.queue_event(NoteEvent::MidiCC { timing: 200, channel: 15, cc: 64, value: 0.0 });
+        host.run(&mut plugin, 256);
+
+        assert_eq!(host.output_events(), [note_on(100, 0, 60), note_off(200, 0, 60)]);
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn test_process_chord_slots() {
+        let (mut host, mut plugin) = start(64);
+        assert!(set_parameter(&plugin, "chord_channel", 15.0));
+        assert!(set_parameter(&plugin, "chord_channels", 2.0));
+        assert!(set_parameter(&plugin, "chord_slot_channel__2", 2.0));
+
+        for (channel, chord) in [(14, [48, 52, 55]), (15, [72, 76, 79])] {
+            for note in chord {
+                host.queue_event(note_on(0, channel, note));
+            }
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.queue_event(note_on(10, 1, 60));
+        host.run(&mut plugin, 64);
+
+        assert_eq!(host.output_events(), [note_on(10, 1, 72), note_on(10, 0, 48)]);
+    }
+
+    #[test]
+    fn test_process_chord_symbol() {
+        let (mut host, mut plugin) = start(64);
+        assert!(set_parameter(&plugin, "chord_symbol_mode", 1.0));
+
+        let symbol_cc = |timing, cc, value: u8| NoteEvent::MidiCC { timing, channel: 15, cc, value: value as f32 / 127.0 };
+        // A minor
+        host.queue_event(symbol_cc(0, 20, 57));
+        host.queue_event(symbol_cc(0, 21, 2));
+        host.queue_event(note_on(10, 0, 60));
+        // the chord notes sound alongside the chord symbol
+        host.queue_event(note_on(100, 15, 72));
+        host.queue_event(symbol_cc(200, 21, 0));
+        host.run(&mut plugin, 256);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 57), note_off(200, 0, 57), note_on(200, 0, 72)]);
+    }
+
+    #[test]
+    fn test_process_chord_memory() {
+        let (mut host, mut plugin) = start(64);
+        assert!(set_parameter(&plugin, "chord_memory", 1.0));
+        assert!(set_parameter(&plugin, "chord_memory_learn", 1.0));
+
+        // C minor is learned as it is held
+        for note in [60, 63, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.run(&mut plugin, 64);
+        assert_eq!(*plugin.params.chord_shape.read().unwrap(), 1 | (1 << 3) | (1 << 7));
+
+        // a single note plays D minor
+        assert!(set_parameter(&plugin, "chord_memory_learn", 0.0));
+        for note in [60, 63, 67] {
+            host.queue_event(note_off(100, 15, note));
+        }
+        host.queue_event(note_on(110, 15, 62));
+        host.queue_event(note_on(120, 0, 61));
+        host.run(&mut plugin, 64);
+
+        assert_eq!(host.output_events(), [note_on(120, 0, 65)]);
+    }
+
+    #[test]
+    fn test_process_strum() {
+        let (mut host, mut plugin) = start(64);
+        assert!(set_parameter(&plugin, "strum_time", 1.0)); // 44 samples
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        for note in [60, 61, 62] {
+            host.queue_event(note_on(10, 0, note));
+        }
+        // the last note of the strum is released before it starts
+        host.queue_event(note_off(60, 0, 62));
+        host.queue_event(note_off(200, 0, 60));
+        host.queue_event(note_off(200, 0, 61));
+        host.run(&mut plugin, 256);
+
+        assert_eq!(host.output_events(), [
+            note_on(10, 0, 60),
+            note_on(54, 0, 64),
+            note_off(200, 0, 64),
+            note_off(200, 0, 60),
+        ]);
+
+        // the notes of a strum start across blocks
+        for note in [60, 61, 62] {
+            host.queue_event(note_on(300, 0, note));
+        }
+        host.run(&mut plugin, 256);
+
+        assert_eq!(&host.output_events()[4..], [note_on(300, 0, 60), note_on(344, 0, 64), note_on(388, 0, 67)]);
+    }
+
+    #[test]
+    fn test_process_strum_beats() {
+        let (mut host, mut plugin) = start(512);
+        assert!(set_parameter(&plugin, "strum_time", 1.0));
+        assert!(set_parameter(&plugin, "strum_beats", 0.02)); // 441 samples at 120 bpm
+        assert!(set_parameter(&plugin, "strum_time_unit", 1.0));
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        for note in [60, 61, 62] {
+            host.queue_event(note_on(10, 0, note));
+        }
+        host.run(&mut plugin, 1024);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_on(451, 0, 64), note_on(892, 0, 67)]);
+    }
+
+    #[test]
+    fn test_process_transport_stop_releases_keys() {
+        let (mut host, mut plugin) = start(64);
+        host.transport.playing = true;
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.run(&mut plugin, 64);
+
+        host.transport.playing = false;
+        host.queue_event(note_off(100, 0, 60));
+        host.run(&mut plugin, 64);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(64, 0, 60)]);
+    }
+
+    #[test]
+    fn test_reset_releases_keys() {
+        let (mut host, mut plugin) = start(64);
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.run(&mut plugin, 64);
+
+        // the chord is forgotten as well
+        plugin.reset();
+        host.queue_event(note_on(100, 0, 60));
+        host.run(&mut plugin, 64);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(64, 0, 60)]);
+    }
+
+    #[test]
+    fn test_process_bridge_across_blocks() {
+        let (mut host, mut plugin) = start(64);
+        assert!(set_parameter(&plugin, "bridge_time", 10.0)); // 441 samples
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+            host.queue_event(note_off(100, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.run(&mut plugin, 1024);
+
+        // the released chord is kept for the bridge time, the end of the bridge is in a later block
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(541, 0, 60)]);
+    }
+
+    #[test]
+    fn test_process_bridge_beats() {
+        let (mut host, mut plugin) = start(512);
+        assert!(set_parameter(&plugin, "bridge_time", 10.0));
+        assert!(set_parameter(&plugin, "bridge_beats", 0.25)); // 5513 samples at 120 bpm
+        assert!(set_parameter(&plugin, "bridge_time_unit", 1.0));
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+            host.queue_event(note_off(100, 15, note));
+        }
+        host.queue_event(note_on(10, 0, 60));
+        host.run(&mut plugin, 8192);
+
+        assert_eq!(host.output_events(), [note_on(10, 0, 60), note_off(5613, 0, 60)]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)] // nih_plug only installs the allocation checking allocator in debug builds
+    fn test_process_does_not_allocate() {
+        let (mut host, mut plugin) = start(512);
+        assert!(set_parameter(&plugin, "strum_time", 1.0));
+        assert!(set_parameter(&plugin, "bridge_time", 1.0));
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        for note in 55..70 {
+            host.queue_event(note_on(10, 0, note));
+        }
+        host.queue_event(NoteEvent::PolyPressure { timing: 20, voice_id: None, channel: 0, note: 61, pressure: 0.5 });
+        host.queue_event(NoteEvent::MidiCC { timing: 20, channel: 0, cc: 1, value: 0.5 });
+        // chord change while the pattern keys are held
+        host.queue_event(note_off(100, 15, 60));
+        host.queue_event(note_on(100, 15, 62));
+        for note in 55..70 {
+            host.queue_event(note_off(300, 0, note));
+        }
+        host.reserve(SEND_EVENT_CAPACITY);
+
+        // aborts the test if the whole block, including the parameter reads of every event, allocates
+        assert_no_alloc::assert_no_alloc(|| host.run(&mut plugin, 512));
+
+        let events = host.output_events();
+        assert!(!events.is_empty());
+        assert_eq!(events.iter().filter(|e| matches!(e, NoteEvent::NoteOn { .. })).count(),
+                   events.iter().filter(|e| matches!(e, NoteEvent::NoteOff { .. })).count());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_transport_stop_does_not_allocate() {
+        let (mut host, mut plugin) = start(512);
+        host.transport.playing = true;
+
+        for note in [60, 64, 67] {
+            host.queue_event(note_on(0, 15, note));
+        }
+        // more keys than one channel can hold
+        for channel in 0..4 {
+            for note in 0..128 {
+                host.queue_event(note_on(10 + channel as u32, channel, note));
+            }
+        }
+        host.run(&mut plugin, 512);
+        let num_notes = host.output_events().len();
+        assert!(num_notes > NUM_KEYS);
+
+        host.transport.playing = false;
+        host.reserve(SEND_EVENT_CAPACITY);
+        assert_no_alloc::assert_no_alloc(|| host.run(&mut plugin, 512));
+
+        assert_eq!(host.output_events().len(), 2 * num_notes);
     }
 }
-*/
\ No newline at end of file
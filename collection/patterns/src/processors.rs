@@ -1,21 +1,136 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::VecDeque;
 use nih_plug::midi::NoteEvent::{NoteOn, NoteOff};
 use nih_plug::prelude::*;
-use crate::active_note::ActiveNoteDefaultData;
+use crate::active_note::{ActiveNoteDefaultData, HeldKeys, NUM_KEYS};
+use crate::chord::Chord;
+use crate::chord_input::ChordInput;
+use crate::chord_memory::ChordShape;
+use crate::chord_symbol::{ChordSymbol, ChordSymbolInput};
+use crate::scale::Scale;
+use crate::strum::{Strummer, StrumSettings};
+use crate::voicing::Voicing;
 
-use crate::utils::{get_note_of_event, get_chord_data, KeyboardMode, raw_note_apply_keyboard_mode};
+use crate::utils::{get_channel_of_event, get_channel_of_message, get_note_of_event, get_velocity_of_event, set_note_of_event, get_bass_separated_chord_data, get_chord_data, ChordChangeMode, ChordOrder, KeyboardMode, NoteRange, PatternMode, ScaleKey, ScaleType, raw_note_apply_keyboard_mode};
 
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Default)]
+/// Maximum number of pressed or released pattern keys that are queued within one cycle. Further events are dropped
+/// instead of growing the queues on the audio thread.
+pub const EVENT_CAPACITY: usize = 512;
+
+/// Upper bound of the events a single `end_cycle` can emit: every queued key event plus a NoteOff/NoteOn pair for each
+/// held key. Buffers passed to `end_cycle` should reserve this much to stay allocation free.
+pub const SEND_EVENT_CAPACITY: usize = 2 * EVENT_CAPACITY + 2 * NUM_CHANNELS * NUM_KEYS;
+
+/// Maximum number of chord channels, each of them feeds its own chord slot.
+pub const MAX_CHORD_SLOTS: usize = 4;
+
+/// Number of MIDI channels.
+pub const NUM_CHANNELS: usize = 16;
+
+/// Number of chord indices, starting with index 0, that have their own velocity offset.
+pub const NUM_VELOCITY_OFFSETS: usize = 4;
+
+/// The parameters that are used to follow the chord and to map the pattern keys to it during a cycle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatternSettings {
+    /// the first chord channel, from 0 to 15
+    pub chord_channel: u8,
+    /// the number of consecutive chord channels starting at the chord channel
+    pub chord_channels: u8,
+    /// the chord slot the pattern keys of each channel follow
+    pub channel_slots: [u8; NUM_CHANNELS],
+    pub wrap_threshold: u8,
+    /// the wrap threshold is the number of chord or scale notes of the slot instead
+    pub auto_threshold: bool,
+    pub chord_symbol: ChordSymbolInput,
+    /// the chord shape a single chord note plays, if the chord memory is on
+    pub chord_memory: Option<ChordShape>,
+    pub octave_range: u8,
+    pub keyboard_mode: KeyboardMode,
+    pub root_note: u8,
+    pub chord_change_mode: ChordChangeMode,
+    pub latch: bool,
+    pub bridge_samples: u64,
+    pub chord_order: ChordOrder,
+    pub pattern_mode: PatternMode,
+    pub scale_type: ScaleType,
+    pub scale_key: ScaleKey,
+    pub voicing: Voicing,
+    pub note_range: NoteRange,
+    /// how much the velocity of the chord note scales the velocity of the pattern note, from 0 to 1
+    pub chord_velocity: f32,
+    /// the note off of a pattern note gets the release velocity of its key instead of the note on velocity
+    pub release_velocity: bool,
+    /// added to the velocity of the pattern notes of the first chord indices
+    pub velocity_offsets: [f32; NUM_VELOCITY_OFFSETS],
+    pub strum: StrumSettings,
+}
+
+impl Default for PatternSettings {
+    fn default() -> Self {
+        Self {
+            chord_channel: 15,
+            chord_channels: 1,
+            channel_slots: [0; NUM_CHANNELS],
+            wrap_threshold: 12,
+            auto_threshold: false,
+            chord_symbol: ChordSymbolInput::default(),
+            chord_memory: None,
+            octave_range: 12,
+            keyboard_mode: KeyboardMode::AllKeys,
+            root_note: 60,
+            chord_change_mode: ChordChangeMode::Retrigger,
+            latch: false,
+            bridge_samples: 0,
+            chord_order: ChordOrder::Ascending,
+            pattern_mode: PatternMode::Chord,
+            scale_type: ScaleType::Chord,
+            scale_key: ScaleKey::C,
+            voicing: Voicing::default(),
+            note_range: NoteRange::default(),
+            chord_velocity: 0.0,
+            release_velocity: false,
+            velocity_offsets: [0.0; NUM_VELOCITY_OFFSETS],
+            strum: StrumSettings::default(),
+        }
+    }
+}
+
+impl PatternSettings {
+    /// The chord slot a channel feeds, if it is a chord channel.
+    pub fn chord_slot(&self, channel: u8) -> Option<usize> {
+        channel.checked_sub(self.chord_channel)
+            .map(usize::from)
+            .filter(|slot| *slot < (self.chord_channels as usize).min(MAX_CHORD_SLOTS))
+    }
+
+    /// The chord slot the pattern keys of a channel follow.
+    pub fn pattern_slot(&self, channel: u8) -> usize {
+        self.channel_slots.get(channel as usize).map_or(0, |slot| *slot as usize).min(MAX_CHORD_SLOTS - 1)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
 pub struct PatternChordData {
     pub chord_idx: u8,
-    pub octave: i8,
+    pub octave: i32,
     pub triggered_note: Option<u8>,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct PatternData {
+    /// The pattern key after the keyboard mode was applied. Several keys can share it, so the held keys are indexed
+    /// by the note of the key itself.
+    pattern_note: u8,
     chord_data: PatternChordData,
     note_data: ActiveNoteDefaultData,
+    /// the key was released while the sustain pedal of its channel was down
+    sustained: bool,
+    /// the velocity of the note on, the key velocity after the chord velocity, the velocity offsets and the strum
+    /// velocity ramp
+    velocity: f32,
+    /// the release velocity of the key, if it is passed on to the note off
+    release_velocity: Option<f32>,
 }
 
 impl PatternData {
@@ -24,7 +139,7 @@ impl PatternData {
             Some(NoteOn {
                 note: modulated_note,
                 channel: self.note_data.channel,
-                velocity: self.note_data.velocity,
+                velocity: self.velocity,
                 voice_id: self.note_data.voice_id,
                 timing: timing,
             })
@@ -36,21 +151,193 @@ impl PatternData {
             Some(NoteOff {
                 note: modulated_note,
                 channel: self.note_data.channel,
-                velocity: self.note_data.velocity,
+                velocity: self.release_velocity.unwrap_or(self.velocity),
                 voice_id: self.note_data.voice_id,
                 timing: timing,
             })
         } else { None }
     }
+
+    /// Moves a held pattern key to the chord data and velocity of a new chord and sends the note events the chord
+    /// change mode asks for.
+    pub fn change_chord<P: nih_plug::prelude::Plugin>(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>,
+                                                      chord_data: PatternChordData, velocity: f32, timing: u32,
+                                                      chord_change_mode: ChordChangeMode) {
+        match chord_change_mode {
+            ChordChangeMode::Retrigger => {
+                if let Some(modulated_event) = self.note_off::<P>(timing) {
+                    send_events.push(modulated_event);
+                }
+                self.chord_data = chord_data;
+                self.velocity = velocity;
+                if let Some(modulated_event) = self.note_on::<P>(timing) {
+                    send_events.push(modulated_event);
+                }
+            }
+            ChordChangeMode::Legato => {
+                let old_note = *self;
+                self.chord_data = chord_data;
+                self.velocity = velocity;
+                // a note that stays the same just keeps sounding
+                if old_note.chord_data.triggered_note != chord_data.triggered_note {
+                    if let Some(modulated_event) = self.note_on::<P>(timing) {
+                        send_events.push(modulated_event);
+                    }
+                    if let Some(modulated_event) = old_note.note_off::<P>(timing) {
+                        send_events.push(modulated_event);
+                    }
+                }
+            }
+            ChordChangeMode::Hold => {
+                // only a key that is not sounding picks up the new chord
+                if self.chord_data.triggered_note.is_none() {
+                    self.chord_data = chord_data;
+                    self.velocity = velocity;
+                    if let Some(modulated_event) = self.note_on::<P>(timing) {
+                        send_events.push(modulated_event);
+                    }
+                }
+            }
+            ChordChangeMode::Release => {
+                if let Some(modulated_event) = self.note_off::<P>(timing) {
+                    send_events.push(modulated_event);
+                }
+                // stays silent until the key is pressed again
+                self.chord_data.triggered_note = None;
+            }
+        }
+    }
+}
+
+/// The chord of one chord channel and the notes the pattern keys that follow it are mapped to.
+#[derive(Debug, Clone, Default)]
+pub struct ChordSlot {
+    pub chord: Chord,
+    pub chord_input: ChordInput,
+    pub chord_symbol: ChordSymbol,
+    /// the notes of the chord symbol
+    symbol_notes: Chord,
+    /// the chord after the voicing was applied
+    voiced_chord: Chord,
+    /// the voiced chord in the order of the chord indices
+    ordered_chord: Chord,
+    /// the last chord that was voice led and the ordered chord it was led from
+    led_chord: Chord,
+    leading_source: Chord,
+    /// the notes the pattern indices are mapped to, the ordered chord or the scale starting on its first note
+    index_notes: Chord,
+    wrap_threshold: u8,
+}
+
+impl ChordSlot {
+    /// Takes the chord from the chord input. With the chord memory a single note plays the stored shape on top of it,
+    /// all notes of the shape with the velocity of that note.
+    fn update_chord(&mut self, settings: &PatternSettings) {
+        self.chord_input.update_chord(&mut self.chord);
+        if let (Some(shape), Some(root)) = (settings.chord_memory, self.chord.lowest()) {
+            if self.chord.len() == 1 && self.chord_input.is_single_key() {
+                shape.transpose(root, self.chord.velocity(root), &mut self.chord);
+            }
+        }
+    }
+
+    fn update_index_notes(&mut self, settings: &PatternSettings) {
+        settings.voicing.apply(&self.chord, &mut self.voiced_chord);
+        self.voiced_chord.order(settings.chord_order, &mut self.ordered_chord);
+
+        if settings.voicing.voice_leading && !self.ordered_chord.is_empty() {
+            // only a new chord is led from the previous one, after a gap the chord before it is the previous one
+            if self.ordered_chord != self.leading_source {
+                self.leading_source.clone_from(&self.ordered_chord);
+                settings.voicing.lead(&self.led_chord, &mut self.ordered_chord);
+                self.led_chord.clone_from(&self.ordered_chord);
+            } else {
+                // the same notes may have been pressed again with another velocity
+                self.led_chord.copy_velocities(&self.ordered_chord);
+                self.ordered_chord.clone_from(&self.led_chord);
+            }
+        }
+
+        match (settings.pattern_mode, self.ordered_chord.as_slice().first()) {
+            (PatternMode::Scale, Some(anchor)) => {
+                let anchor = *anchor;
+                Scale::for_chord(settings.scale_type, settings.scale_key as u8, &self.chord)
+                    .notes_from(anchor, &mut self.index_notes);
+                // the scale tones of the chord have the velocity of their chord note
+                for note in self.ordered_chord.iter().copied() {
+                    let scale_note = anchor + (note % 12 + 12 - anchor % 12) % 12;
+                    self.index_notes.set_velocity(scale_note, self.ordered_chord.velocity(note));
+                }
+            }
+            (PatternMode::Scale, None) => self.index_notes.clear(),
+            (PatternMode::Chord, _) => self.index_notes.clone_from(&self.ordered_chord),
+        }
+
+        self.wrap_threshold = if settings.auto_threshold {
+            let notes = match settings.pattern_mode {
+                PatternMode::Chord => self.voiced_chord.len(),
+                PatternMode::Scale => Scale::for_chord(settings.scale_type, settings.scale_key as u8, &self.chord).len(),
+            };
+            (notes as u8).max(1) // minimum wrap threshold of 1 to not divide by zero
+        } else {
+            settings.wrap_threshold
+        };
+    }
+
+    fn chord_data(&self, pattern_note: u8, settings: &PatternSettings) -> PatternChordData {
+        match (settings.chord_order, settings.pattern_mode) {
+            (ChordOrder::BassSeparated, PatternMode::Chord) => get_bass_separated_chord_data(
+                self.index_notes.as_slice(), pattern_note, self.wrap_threshold, settings.octave_range,
+                settings.root_note, settings.note_range),
+            _ => get_chord_data(self.index_notes.as_slice(), pattern_note, self.wrap_threshold, settings.octave_range,
+                                settings.root_note, settings.note_range),
+        }
+    }
+
+    /// The velocity of a pattern note: the key velocity scaled by the velocity of the chord note it plays, plus the
+    /// offset of its chord index. Scale notes outside the chord and the notes of a chord symbol count as full velocity.
+    fn velocity(&self, key_velocity: f32, chord_data: &PatternChordData, settings: &PatternSettings) -> f32 {
+        let chord_idx = chord_data.chord_idx as usize;
+        let chord_velocity = self.index_notes.as_slice().get(chord_idx)
+            .and_then(|note| self.index_notes.velocity(*note))
+            .unwrap_or(1.0);
+        let scaled = key_velocity * (1.0 - settings.chord_velocity + settings.chord_velocity * chord_velocity);
+        let offset = settings.velocity_offsets.get(chord_idx).copied().unwrap_or_default();
+        (scaled + offset).clamp(0.0, 1.0)
+    }
 }
 
-#[derive(Default)]
 pub struct ChordPatternProcessor<P: nih_plug::prelude::Plugin> {
     pub pressed_pattern_keys: VecDeque<PluginNoteEvent<P>>,
     pub released_pattern_keys: VecDeque<PluginNoteEvent<P>>,
 
-    pub held_pattern_keys: BTreeMap<u8, PatternData>,
-    pub chord: BTreeSet<u8>,
+    /// the held keys of each pattern channel, the same key can be held on several channels
+    pub held_pattern_keys: [HeldKeys<PatternData>; NUM_CHANNELS],
+    /// one slot per chord channel
+    pub slots: [ChordSlot; MAX_CHORD_SLOTS],
+
+    /// one bit per pattern channel whose sustain pedal is down
+    sustained_channels: u16,
+
+    /// the note ons of a strum that have not started yet
+    strummer: Strummer<P>,
+
+    /// absolute sample position of the current block, the chord input keeps time across blocks
+    block_start: u64,
+}
+
+impl <P: nih_plug::prelude::Plugin> Default for ChordPatternProcessor<P> {
+    fn default() -> Self {
+        Self {
+            pressed_pattern_keys: VecDeque::with_capacity(EVENT_CAPACITY),
+            released_pattern_keys: VecDeque::with_capacity(EVENT_CAPACITY),
+            held_pattern_keys: Default::default(),
+            slots: Default::default(),
+            sustained_channels: 0,
+            strummer: Strummer::default(),
+            block_start: 0,
+        }
+    }
 }
 
 impl <P: nih_plug::prelude::Plugin> ChordPatternProcessor<P> {
@@ -59,13 +346,27 @@ impl <P: nih_plug::prelude::Plugin> ChordPatternProcessor<P> {
         process_note_event(e)
     } */
 
-    fn apply_pattern_changes(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32,
-                             wrap_threshold: u8, octave_range: u8, keyboard_mode: KeyboardMode) {
+    fn apply_pattern_changes(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32, settings: &PatternSettings) {
+        for slot in self.slots.iter_mut() {
+            slot.update_index_notes(settings);
+        }
+
         // released keys
         while let Some(note_event) = self.released_pattern_keys.pop_back() {
-            if let Some(raw_note) = get_note_of_event::<P>(&note_event)
-                                        .and_then(|note| raw_note_apply_keyboard_mode(note, &keyboard_mode)){
-                if let Some(active_note) = self.held_pattern_keys.remove(&raw_note) {
+            if let Some((channel, key)) = get_key_of_event::<P>(&note_event) {
+                let held_keys = &mut self.held_pattern_keys[channel];
+                // the sustain pedal keeps the note, it still follows chord changes until the pedal is released
+                if let Some(active_note) = held_keys.get_mut(key) {
+                    if settings.release_velocity {
+                        active_note.release_velocity = get_velocity_of_event::<P>(&note_event);
+                    }
+                    if self.sustained_channels & channel_bit(active_note.note_data.channel) != 0 {
+                        active_note.sustained = true;
+                        continue;
+                    }
+                }
+                // the key is released exactly as it was pressed, whatever it is mapped to
+                if let Some(active_note) = held_keys.remove(key) {
                     if let Some(modulated_event) = active_note.note_off::<P>(note_event.timing()) {
                         send_events.push(modulated_event);
                     }
@@ -74,47 +375,83 @@ impl <P: nih_plug::prelude::Plugin> ChordPatternProcessor<P> {
         }
 
         // changes in chord
-        for (idx, e) in self.held_pattern_keys.iter_mut() {
-            let chord_data = get_chord_data(&self.chord.iter().cloned().collect(), *idx, wrap_threshold, octave_range);
+        for (_, e) in self.held_pattern_keys.iter_mut().flat_map(|held_keys| held_keys.iter_mut()) {
+            let slot = &self.slots[settings.pattern_slot(e.note_data.channel)];
+            let chord_data = slot.chord_data(e.pattern_note, settings);
             if e.chord_data != chord_data { // chord changed
-                // release notes if triggered
-                if let Some(modulated_event) = e.note_off::<P>(timing) {
-                    send_events.push(modulated_event);
+                let velocity = slot.velocity(e.note_data.velocity, &chord_data, settings);
+                e.change_chord::<P>(send_events, chord_data, velocity, timing, settings.chord_change_mode);
+            }
+        }
+
+        // pressed keys
+        while let Some(note_event) = self.pressed_pattern_keys.pop_back() {
+            if let Some((channel, key)) = get_key_of_event::<P>(&note_event) {
+                // a key that is pressed again without a release in between stops its note first, so it cannot hang
+                if let Some(active_note) = self.held_pattern_keys[channel].remove(key) {
+                    if let Some(modulated_event) = active_note.note_off::<P>(note_event.timing()) {
+                        send_events.push(modulated_event);
+                    }
                 }
 
-                e.chord_data = chord_data; //update chord data
+                if let Some(pattern_note) = raw_note_apply_keyboard_mode(key, &settings.keyboard_mode, settings.root_note) {
+                    let note_data = ActiveNoteDefaultData::from_note_event::<P>(&note_event);
+                    let slot = &self.slots[settings.pattern_slot(note_data.channel)];
+                    let chord_data = slot.chord_data(pattern_note, settings);
 
-                // press note if triggered note is valid
-                if let Some(modulated_event) = e.note_on::<P>(timing) {
-                    send_events.push(modulated_event);
+                    let active_note = PatternData {
+                      pattern_note,
+                      chord_data,
+                      note_data,
+                      sustained: false,
+                      velocity: slot.velocity(note_data.velocity, &chord_data, settings),
+                      release_velocity: None,
+                    };
+
+                    if let Some(modulated_event) = active_note.note_on::<P>(note_event.timing()) {
+                      send_events.push(modulated_event);
+                    }
+                    self.held_pattern_keys[channel].insert(key, active_note);
                 }
             }
         }
+    }
 
-        // pressed keys
-        while let Some(note_event) = self.pressed_pattern_keys.pop_back() {
-            if let Some(raw_note) = get_note_of_event::<P>(&note_event)
-                                        .and_then(|note| raw_note_apply_keyboard_mode(note, &keyboard_mode)){
-                let chord_data = get_chord_data(&self.chord.iter().cloned().collect(), raw_note, wrap_threshold, octave_range);
+    //----------------------------
 
-                let active_note = PatternData {
-                  chord_data: chord_data,
-                  note_data: ActiveNoteDefaultData::from_note_event::<P>(&note_event),
-                };
+    pub fn end_cycle(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32, settings: &PatternSettings) {
+        let start = send_events.len();
+        self.apply_pattern_changes(send_events, timing, settings);
+        self.strummer.strum(send_events, start, self.block_start + timing as u64, &settings.strum);
 
-                if let Some(modulated_event) = active_note.note_on::<P>(note_event.timing()) {
-                  send_events.push(modulated_event);
+        // the note offs of strummed notes repeat the velocity the velocity ramp gave their note ons
+        if settings.strum.velocity_ramp > 0.0 {
+            let strummer = &self.strummer;
+            for (_, active_note) in self.held_pattern_keys.iter_mut().flat_map(|held_keys| held_keys.iter_mut()) {
+                let held_back_velocity = active_note.chord_data.triggered_note.and_then(|note| {
+                    strummer.held_back_velocity(note, active_note.note_data.channel, active_note.note_data.voice_id)
+                });
+                if let Some(velocity) = held_back_velocity {
+                    active_note.velocity = velocity;
                 }
-                self.held_pattern_keys.insert(raw_note, active_note);
-          }
+            }
         }
     }
 
-    //----------------------------
+    /// Timing within the current block at which the next note of a strum starts.
+    pub fn next_strum_note(&self) -> Option<u32> {
+        self.strummer.next_due()
+            .map(|due| due.saturating_sub(self.block_start).min(u32::MAX as u64) as u32)
+    }
 
-    pub fn end_cycle(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32, wrap_threshold: u8,
-                     octave_range: u8, keyboard_mode: KeyboardMode) {
-        self.apply_pattern_changes(send_events, timing, wrap_threshold, octave_range, keyboard_mode);
+    /// Sends the notes of strums that are due by the timing.
+    pub fn send_strum_notes(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32) {
+        while let Some((due, event)) = self.strummer.pop_due(self.block_start + timing as u64) {
+            if let NoteOn { note, velocity, voice_id, channel, .. } = event {
+                let timing = due.saturating_sub(self.block_start) as u32;
+                send_events.push(NoteOn { note, velocity, voice_id, channel, timing });
+            }
+        }
     }
 
     //----------------------------
@@ -129,23 +466,189 @@ impl <P: nih_plug::prelude::Plugin> ChordPatternProcessor<P> {
     }*/
     //----------------------------
 
-    pub fn process_chord_event(&mut self, e: PluginNoteEvent<P>) {
+    pub fn start_block(&mut self, block_start: u64) {
+        self.block_start = block_start;
+    }
+
+    //----------------------------
+
+    /// Feeds a note or a chord symbol message of a chord channel to the chord slot of the channel.
+    pub fn process_chord_event(&mut self, e: PluginNoteEvent<P>, settings: &PatternSettings) {
+        let now = self.block_start + e.timing() as u64;
+        // the caller decides which events are chord events, those of a channel without a slot feed the first one
+        let slot = get_channel_of_message::<P>(&e).and_then(|channel| settings.chord_slot(channel)).unwrap_or(0);
+        let slot = &mut self.slots[slot];
         match e {
-            NoteOn{note, ..} => {let _ = self.chord.insert(note);},
-            NoteOff{note, ..} => {let _ = self.chord.remove(&note);},
-            _ => {}
+            NoteOn{note, velocity, ..} => {
+                slot.chord_input.note_on(note, velocity, now, settings.latch, settings.bridge_samples)
+            }
+            NoteOff{note, ..} => slot.chord_input.note_off(note, now, settings.latch, settings.bridge_samples),
+            _ if settings.chord_symbol.process::<P>(&e, &mut slot.chord_symbol) => {
+                slot.chord_symbol.expand(&mut slot.symbol_notes);
+                slot.chord_input.set_symbol(&slot.symbol_notes);
+            }
+            _ => return,
+        }
+        slot.update_chord(settings);
+    }
+
+    //----------------------------
+
+    /// Presses or releases the sustain pedal of a chord channel. Chord keys released while it is down stay in the
+    /// chord until it is released.
+    pub fn sustain_chord(&mut self, channel: u8, sustain: bool, timing: u32, settings: &PatternSettings) {
+        let now = self.block_start + timing as u64;
+        if let Some(slot) = settings.chord_slot(channel).map(|slot| &mut self.slots[slot]) {
+            slot.chord_input.set_sustain(sustain, now, settings.latch, settings.bridge_samples);
+            slot.update_chord(settings);
+        }
+    }
+
+    //----------------------------
+
+    /// Timing within the current block at which a chord changes next without any chord input, because a bridged
+    /// gap or overlap ends.
+    pub fn next_chord_change(&self) -> Option<u32> {
+        self.slots.iter()
+            .filter_map(|slot| slot.chord_input.next_deadline())
+            .min()
+            .map(|deadline| deadline.saturating_sub(self.block_start).min(u32::MAX as u64) as u32)
+    }
+
+    pub fn expire_chord_notes(&mut self, timing: u32, settings: &PatternSettings) {
+        for slot in self.slots.iter_mut() {
+            slot.chord_input.expire(self.block_start + timing as u64);
+            slot.update_chord(settings);
+        }
+    }
+
+    /// Forgets the held and latched keys of a chord channel, e.g. on its panic message.
+    pub fn release_chord(&mut self, channel: u8, settings: &PatternSettings) {
+        if let Some(slot) = settings.chord_slot(channel).map(|slot| &mut self.slots[slot]) {
+            slot.chord_input = ChordInput::default();
+            slot.chord_symbol = ChordSymbol::default();
+            slot.update_chord(settings);
+        }
+    }
+
+    pub fn is_chord_latched(&self) -> bool {
+        self.slots.iter().any(|slot| slot.chord_input.is_latched())
+    }
+
+    pub fn unlatch_chord(&mut self, settings: &PatternSettings) {
+        for slot in self.slots.iter_mut() {
+            slot.chord_input.unlatch();
+            slot.update_chord(settings);
         }
     }
 
     //----------------------------
 
     pub fn process_pattern_event(&mut self, e: PluginNoteEvent<P>) {
-        match e {
-            NoteOn{..} => self.pressed_pattern_keys.push_back(e),
-            NoteOff{..} => self.released_pattern_keys.push_back(e),
-            _ => {}
+        let queue = match e {
+            NoteOn{..} => &mut self.pressed_pattern_keys,
+            NoteOff{..} => {
+                // releases are applied before presses, so a key pressed and released at the same time is not pressed
+                // at all
+                let key = get_key_of_event::<P>(&e);
+                self.pressed_pattern_keys.retain(|pressed| get_key_of_event::<P>(pressed) != key);
+                &mut self.released_pattern_keys
+            }
+            _ => return,
+        };
+        // never grow the queue on the audio thread
+        if queue.len() < EVENT_CAPACITY {
+            queue.push_back(e);
         }
     }
+
+    /// Presses or releases the sustain pedal of a pattern channel. Releasing it ends the notes of the keys of the
+    /// channel that were released while it was down.
+    pub fn sustain_pattern_keys(&mut self, channel: u8, sustain: bool, send_events: &mut Vec<PluginNoteEvent<P>>,
+                                timing: u32) {
+        if sustain {
+            self.sustained_channels |= channel_bit(channel);
+            return;
+        }
+
+        self.sustained_channels &= !channel_bit(channel);
+        let held_keys = match self.held_pattern_keys.get_mut(channel as usize) {
+            Some(held_keys) => held_keys,
+            None => return,
+        };
+        let start = send_events.len();
+        for key in 0..NUM_KEYS as u8 {
+            if held_keys.get(key).is_some_and(|active_note| active_note.sustained) {
+                if let Some(modulated_event) = held_keys.remove(key).and_then(|n| n.note_off::<P>(timing)) {
+                    send_events.push(modulated_event);
+                }
+            }
+        }
+        self.strummer.cancel(send_events, start);
+    }
+
+    /// Ends the notes of all held pattern keys. The keys stay silent until they are pressed again.
+    pub fn release_pattern_keys(&mut self, send_events: &mut Vec<PluginNoteEvent<P>>, timing: u32) {
+        let start = send_events.len();
+        for held_keys in self.held_pattern_keys.iter_mut() {
+            for (_, active_note) in held_keys.iter() {
+                if let Some(modulated_event) = active_note.note_off::<P>(timing) {
+                    send_events.push(modulated_event);
+                }
+            }
+            held_keys.clear();
+        }
+        self.strummer.cancel(send_events, start);
+    }
+
+    /// Forgets all chord and pattern input without allocating. Release the pattern keys first to end their notes.
+    pub fn reset(&mut self) {
+        self.pressed_pattern_keys.clear();
+        self.released_pattern_keys.clear();
+        for held_keys in self.held_pattern_keys.iter_mut() {
+            held_keys.clear();
+        }
+        for slot in self.slots.iter_mut() {
+            *slot = ChordSlot::default();
+        }
+        self.sustained_channels = 0;
+        self.strummer.clear();
+        self.block_start = 0;
+    }
+
+    //----------------------------
+
+    /// Maps a non-note event of a pattern channel to the note that is currently triggered by its pattern key.
+    /// Polyphonic events are remapped through the held pattern keys and dropped if the key does not trigger a note or
+    /// its note is held back by a strum and has not started yet. Channel-wide events (CC, pitch bend, channel pressure,
+    /// ...) are passed through unchanged.
+    pub fn modulate_event(&self, note_event: &PluginNoteEvent<P>) -> Option<PluginNoteEvent<P>> {
+        match get_note_of_event::<P>(note_event) {
+            Some(_) => {
+                let (channel, key) = get_key_of_event::<P>(note_event)?;
+                let active_note = self.held_pattern_keys[channel].get(key)?;
+                let triggered_note = active_note.chord_data.triggered_note?;
+                let note_data = &active_note.note_data;
+                if self.strummer.held_back_velocity(triggered_note, note_data.channel, note_data.voice_id).is_some() {
+                    return None;
+                }
+                Some(set_note_of_event::<P>(note_event, triggered_note))
+            }
+            None => Some(*note_event),
+        }
+    }
+}
+
+/// The channel and the key of a note event. Channels beyond 16 share the held keys of the last one.
+fn get_key_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNoteEvent<P>) -> Option<(usize, u8)> {
+    let channel = get_channel_of_event::<P>(note_event)?;
+    let key = get_note_of_event::<P>(note_event)?;
+    Some(((channel as usize).min(NUM_CHANNELS - 1), key))
+}
+
+/// The bit of a channel in a set of channels. Channels beyond 16 have none.
+fn channel_bit(channel: u8) -> u16 {
+    1u16.checked_shl(channel as u32).unwrap_or(0)
 }
 
 
@@ -154,12 +657,15 @@ impl <P: nih_plug::prelude::Plugin> ChordPatternProcessor<P> {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::BTreeSet;
     use nih_plug::midi::PluginNoteEvent;
-    use nih_plug::midi::NoteEvent::{NoteOn, NoteOff};
+    use nih_plug::midi::NoteEvent::{NoteOn, NoteOff, PolyPressure, PolyExpression, MidiCC};
     use crate::Patterns;
-    use crate::processors::{ChordPatternProcessor};
-    use crate::utils::KeyboardMode;
+    use crate::chord::Chord;
+    use crate::chord_memory::ChordShape;
+    use crate::processors::{ChordPatternProcessor, PatternSettings, NUM_CHANNELS, SEND_EVENT_CAPACITY};
+    use crate::strum::StrumSettings;
+    use crate::utils::{ChordChangeMode, ChordOrder, KeyboardMode, NoteRange, OutOfRange, PatternMode, ScaleKey, ScaleType};
+    use crate::voicing::Voicing;
 
     #[test]
     fn test_process_chord_event() {
@@ -172,10 +678,9 @@ mod tests {
             voice_id: None,
             timing: 0,
             channel: 16
-        });
+        }, &PatternSettings::default());
 
-        let chord_vec: Vec<u8> = processor.chord.clone().into_iter().collect();
-        assert_eq!(chord_vec, [60]);
+        assert_eq!(processor.slots[0].chord.as_slice(), [60]);
 
         processor.process_chord_event(NoteOff {
             note:60,
@@ -183,11 +688,9 @@ mod tests {
             voice_id: None,
             timing: 0,
             channel: 16
-        });
+        }, &PatternSettings::default());
 
-        let chord_vec: Vec<u8> = processor.chord.clone().into_iter().collect();
-        println!("{:?}", chord_vec);
-        assert!(chord_vec.is_empty());
+        assert!(processor.slots[0].chord.is_empty());
     }
 
     #[test]
@@ -237,7 +740,7 @@ mod tests {
         // note on
         let mut processor = ChordPatternProcessor::<Patterns>::default();
 
-        processor.chord = BTreeSet::from([72, 74, 76]);
+        processor.slots[0].chord = Chord::from([72, 74, 76]);
 
         // press pattern note
         //---------------------------------->
@@ -250,7 +753,7 @@ mod tests {
         });
 
         let send_events = &mut vec![];
-        processor.end_cycle(send_events, 0, 3, 12, KeyboardMode::AllKeys);
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, ..Default::default() });
 
         assert_eq!(*send_events, [
             NoteOn {
@@ -278,7 +781,7 @@ mod tests {
         });
 
         let send_events = &mut vec![];
-        processor.end_cycle(send_events, 1, 3, 12, KeyboardMode::AllKeys);
+        processor.end_cycle(send_events, 1, &PatternSettings { wrap_threshold: 3, ..Default::default() });
 
         assert_eq!(*send_events, [
             NoteOff {
@@ -291,4 +794,697 @@ mod tests {
         //<----------------------------------
 
     }
-}
\ No newline at end of file
+
+    /// Presses the pattern key C#3 on the chord C3 E3 G3 and changes the chord to D3 F3 A3 while the key is held.
+    fn run_chord_change(chord_change_mode: ChordChangeMode) -> (ChordPatternProcessor<Patterns>, Vec<PluginNoteEvent<Patterns>>) {
+        let settings = PatternSettings { wrap_threshold: 3, chord_change_mode, ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        processor.slots[0].chord = Chord::from([60, 64, 67]);
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        processor.slots[0].chord = Chord::from([62, 65, 69]);
+
+        let mut send_events = vec![];
+        processor.end_cycle(&mut send_events, 5, &settings);
+        (processor, send_events)
+    }
+
+    fn release_chord_change_key(processor: &mut ChordPatternProcessor<Patterns>, chord_change_mode: ChordChangeMode) -> Vec<PluginNoteEvent<Patterns>> {
+        let settings = PatternSettings { wrap_threshold: 3, chord_change_mode, ..Default::default() };
+
+        processor.process_pattern_event(NoteOff { note: 61, velocity: 0.0, voice_id: None, timing: 10, channel: 0 });
+
+        let mut send_events = vec![];
+        processor.end_cycle(&mut send_events, 10, &settings);
+        send_events
+    }
+
+    #[test]
+    fn test_chord_change_retrigger() {
+        let (mut processor, send_events) = run_chord_change(ChordChangeMode::Retrigger);
+
+        assert_eq!(send_events, [
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+            NoteOn { note: 65, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+        ]);
+
+        assert_eq!(release_chord_change_key(&mut processor, ChordChangeMode::Retrigger), [
+            NoteOff { note: 65, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_chord_change_legato() {
+        let (mut processor, send_events) = run_chord_change(ChordChangeMode::Legato);
+
+        assert_eq!(send_events, [
+            NoteOn { note: 65, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+        ]);
+
+        // a chord change that keeps the note does not send anything
+        processor.slots[0].chord = Chord::from([62, 65, 70]);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 7, &PatternSettings { wrap_threshold: 3, chord_change_mode: ChordChangeMode::Legato, ..Default::default() });
+        assert!(send_events.is_empty());
+
+        assert_eq!(release_chord_change_key(&mut processor, ChordChangeMode::Legato), [
+            NoteOff { note: 65, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_chord_change_hold() {
+        let (mut processor, send_events) = run_chord_change(ChordChangeMode::Hold);
+
+        assert!(send_events.is_empty());
+
+        // the old note is released with the pattern key
+        assert_eq!(release_chord_change_key(&mut processor, ChordChangeMode::Hold), [
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_chord_change_release() {
+        let (mut processor, send_events) = run_chord_change(ChordChangeMode::Release);
+
+        assert_eq!(send_events, [
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+        ]);
+
+        // further chord changes do not retrigger the key
+        processor.slots[0].chord = Chord::from([60, 64, 67]);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 7, &PatternSettings { wrap_threshold: 3, chord_change_mode: ChordChangeMode::Release, ..Default::default() });
+        assert!(send_events.is_empty());
+
+        assert!(release_chord_change_key(&mut processor, ChordChangeMode::Release).is_empty());
+        assert!(processor.held_pattern_keys.iter().all(|held_keys| held_keys.iter().next().is_none()));
+    }
+
+    #[test]
+    fn test_modulate_event() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        processor.slots[0].chord = Chord::from([72, 74, 76]);
+
+        processor.process_pattern_event(NoteOn {
+            note:61,
+            velocity: 1.0,
+            voice_id: None,
+            timing: 0,
+            channel: 0
+        });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+
+        // poly events of a held key are mapped to the triggered note
+        let modulated_event = processor.modulate_event(&PolyPressure {
+            note: 61,
+            pressure: 0.5,
+            voice_id: None,
+            timing: 5,
+            channel: 0
+        });
+
+        assert_eq!(modulated_event, Some(PolyPressure {
+            note: 74,
+            pressure: 0.5,
+            voice_id: None,
+            timing: 5,
+            channel: 0
+        }));
+
+        // the mapping follows chord changes
+        processor.slots[0].chord = Chord::from([60, 65, 69]);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 6, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+
+        let modulated_event = processor.modulate_event(&PolyExpression {
+            note: 61,
+            expression: 0.25,
+            voice_id: None,
+            timing: 7,
+            channel: 0
+        });
+
+        assert_eq!(modulated_event, Some(PolyExpression {
+            note: 65,
+            expression: 0.25,
+            voice_id: None,
+            timing: 7,
+            channel: 0
+        }));
+
+        // poly events of keys that are not held are dropped
+        let modulated_event = processor.modulate_event(&PolyPressure {
+            note: 62,
+            pressure: 0.5,
+            voice_id: None,
+            timing: 7,
+            channel: 0
+        });
+
+        assert_eq!(modulated_event, None);
+
+        // channel-wide events are passed through
+        let cc_event = MidiCC {
+            cc: 1,
+            value: 0.5,
+            timing: 7,
+            channel: 0
+        };
+        assert_eq!(processor.modulate_event(&cc_event), Some(cc_event));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)] // nih_plug only installs the allocation checking allocator in debug builds
+    fn test_end_cycle_does_not_allocate() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        let mut send_events = Vec::with_capacity(SEND_EVENT_CAPACITY);
+
+        // aborts the test if anything in here allocates
+        assert_no_alloc::assert_no_alloc(|| {
+            for note in [60, 64, 67] {
+                processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+            }
+            for note in 55..70 {
+                processor.process_pattern_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+            }
+            processor.end_cycle(&mut send_events, 0, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+
+            // chord change while the pattern keys are held
+            processor.process_chord_event(NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 10, channel: 15 }, &PatternSettings::default());
+            processor.process_chord_event(NoteOn { note: 62, velocity: 1.0, voice_id: None, timing: 10, channel: 15 }, &PatternSettings::default());
+            processor.end_cycle(&mut send_events, 10, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+
+            // poly events follow the held keys
+            let _ = processor.modulate_event(&PolyPressure { note: 61, pressure: 0.5, voice_id: None, timing: 15, channel: 0 });
+
+            for note in 55..70 {
+                processor.process_pattern_event(NoteOff { note, velocity: 1.0, voice_id: None, timing: 20, channel: 0 });
+            }
+            processor.end_cycle(&mut send_events, 20, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+        });
+
+        assert_eq!(send_events.iter().filter(|e| matches!(e, NoteOn { .. })).count(),
+                   send_events.iter().filter(|e| matches!(e, NoteOff { .. })).count());
+        assert!(processor.held_pattern_keys.iter().all(|held_keys| held_keys.iter().next().is_none()));
+    }
+
+    #[test]
+    fn test_end_cycle_shift_black_keys() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        processor.slots[0].chord = Chord::from([60, 64, 67]);
+
+        // C#3 and D3 share the chord index of D3
+        processor.process_pattern_event(NoteOn { note: 62, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 0.5, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, keyboard_mode: KeyboardMode::ShiftBlackKeysRight, ..Default::default() });
+
+        assert_eq!(*send_events, [
+            NoteOn { note: 64, velocity: 0.5, voice_id: None, timing: 0, channel: 0 },
+            NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 0 },
+        ]);
+
+        // releasing the black key only releases its own note
+        processor.process_pattern_event(NoteOff { note: 61, velocity: 0.0, voice_id: None, timing: 1, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 1, &PatternSettings { wrap_threshold: 3, keyboard_mode: KeyboardMode::ShiftBlackKeysRight, ..Default::default() });
+
+        assert_eq!(*send_events, [
+            NoteOff { note: 64, velocity: 0.5, voice_id: None, timing: 1, channel: 0 },
+        ]);
+        assert!(processor.held_pattern_keys[0].get(62).is_some());
+
+        // C#3 shifted to the left plays the chord index of C3
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 1.0, voice_id: None, timing: 2, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 2, &PatternSettings { wrap_threshold: 3, keyboard_mode: KeyboardMode::ShiftBlackKeysLeft, ..Default::default() });
+
+        assert_eq!(*send_events, [
+            NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 2, channel: 0 },
+        ]);
+
+        // the note off matches the note on, even if the keyboard mode changed in between
+        processor.process_pattern_event(NoteOff { note: 61, velocity: 0.0, voice_id: None, timing: 3, channel: 0 });
+        processor.process_pattern_event(NoteOff { note: 62, velocity: 0.0, voice_id: None, timing: 3, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 3, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+
+        assert_eq!(*send_events, [
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 3, channel: 0 },
+            NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 3, channel: 0 },
+        ]);
+        assert!(processor.held_pattern_keys.iter().all(|held_keys| held_keys.iter().next().is_none()));
+    }
+
+    #[test]
+    fn test_latch_chord() {
+        let settings = PatternSettings { wrap_threshold: 3, latch: true, ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        processor.process_chord_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // the pattern note keeps sounding when the chord track goes empty
+        processor.process_chord_event(NoteOff { note: 60, velocity: 0.0, voice_id: None, timing: 5, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOff { note: 64, velocity: 0.0, voice_id: None, timing: 5, channel: 15 }, &settings);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 5, &settings);
+        assert!(send_events.is_empty());
+
+        // until the next chord arrives
+        processor.process_chord_event(NoteOn { note: 62, velocity: 1.0, voice_id: None, timing: 9, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOn { note: 65, velocity: 1.0, voice_id: None, timing: 9, channel: 15 }, &settings);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 9, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 9, channel: 0 },
+            NoteOn { note: 65, velocity: 1.0, voice_id: None, timing: 9, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_bridge_chord_gap() {
+        let settings = PatternSettings { wrap_threshold: 3, bridge_samples: 100, ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        processor.start_block(1000);
+        processor.process_chord_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // a gap in the chord track does not cut the pattern note
+        processor.process_chord_event(NoteOff { note: 60, velocity: 0.0, voice_id: None, timing: 450, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOff { note: 64, velocity: 0.0, voice_id: None, timing: 450, channel: 15 }, &settings);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 450, &settings);
+        assert!(send_events.is_empty());
+
+        // the bridge ends in the next block
+        processor.start_block(1512);
+        assert_eq!(processor.next_chord_change(), Some(38));
+        processor.expire_chord_notes(38, &settings);
+        assert_eq!(processor.next_chord_change(), None);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 38, &settings);
+        assert_eq!(*send_events, [NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 38, channel: 0 }]);
+    }
+
+    #[test]
+    fn test_chord_order() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        // G3 is pressed before C3 and E3
+        for note in [67, 60, 64] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, chord_order: ChordOrder::Arrival, ..Default::default() });
+        assert_eq!(*send_events, [NoteOn { note: 67, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // G3 is the first note in descending order as well, the held key keeps its note
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 1, &PatternSettings { wrap_threshold: 3, chord_order: ChordOrder::Descending, ..Default::default() });
+        assert!(send_events.is_empty());
+
+        // switching to ascending order moves the held key to another chord note
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 2, &PatternSettings { wrap_threshold: 3, chord_order: ChordOrder::Ascending, ..Default::default() });
+        assert_eq!(*send_events, [
+            NoteOff { note: 67, velocity: 1.0, voice_id: None, timing: 2, channel: 0 },
+            NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 2, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_bass_separated() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        for note in [67, 60, 64] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+        }
+
+        // the root key plays the bass, the keys above it wrap around G3 and E3 in the order they were pressed
+        let mut played = vec![];
+        for chord_order in [ChordOrder::BassSeparated, ChordOrder::Ascending] {
+            let settings = PatternSettings { wrap_threshold: 3, chord_order, ..Default::default() };
+            for note in 58..65 {
+                processor.process_pattern_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+                let send_events = &mut vec![];
+                processor.end_cycle(send_events, 0, &settings);
+                played.extend(send_events.iter().filter_map(|e| match e {
+                    NoteOn { note, .. } => Some(*note),
+                    _ => None
+                }));
+                processor.release_pattern_keys(&mut vec![], 0);
+            }
+        }
+        assert_eq!(played, [
+            55, 52, 60, 67, 64, 79, 76, // bass separated
+            52, 55, 60, 64, 67, 72, 76, // ascending
+        ]);
+    }
+
+    #[test]
+    fn test_scale_pattern_mode() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        for note in [57, 60, 64] { // A minor
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+        }
+
+        let settings = PatternSettings { wrap_threshold: 7, pattern_mode: PatternMode::Scale, ..Default::default() };
+
+        // a run over the pattern keys walks the A minor scale, the chord tones are on every other key
+        let mut played = vec![];
+        for (timing, note) in (60..68).enumerate() {
+            processor.process_pattern_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: timing as u32, channel: 0 });
+            let send_events = &mut vec![];
+            processor.end_cycle(send_events, timing as u32, &settings);
+            played.extend(send_events.iter().filter_map(|e| match e {
+                NoteOn { note, .. } => Some(*note),
+                _ => None
+            }));
+        }
+        assert_eq!(played, [57, 59, 60, 62, 64, 65, 67, 69]);
+
+        // a fixed scale, the held keys follow it: the B of the second key becomes a Bb in F major. The new key two
+        // degrees below the root plays F.
+        let settings = PatternSettings { scale_type: ScaleType::Major, scale_key: ScaleKey::F, ..settings };
+        processor.process_pattern_event(NoteOn { note: 58, velocity: 1.0, voice_id: None, timing: 10, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 10, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 59, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+            NoteOn { note: 58, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+            NoteOn { note: 53, velocity: 1.0, voice_id: None, timing: 10, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_voicing() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        for note in [60, 64, 67] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        // a bass pattern plays the chord in the bass range
+        let bass = Voicing { window_low: 36, window_high: 47, ..Default::default() };
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, voicing: bass, ..Default::default() });
+        assert_eq!(*send_events, [NoteOn { note: 36, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // the first inversion starts on the third
+        let first_inversion = Voicing { inversion: 1, ..Default::default() };
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 1, &PatternSettings { wrap_threshold: 3, voicing: first_inversion, ..Default::default() });
+        assert_eq!(*send_events, [
+            NoteOff { note: 36, velocity: 1.0, voice_id: None, timing: 1, channel: 0 },
+            NoteOn { note: 64, velocity: 1.0, voice_id: None, timing: 1, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_voice_leading() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        let settings = PatternSettings { wrap_threshold: 3, voicing: Voicing { voice_leading: true, ..Default::default() }, ..Default::default() };
+
+        for note in [60, 64, 67] { // C major
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // G major played above C major is led down
+        for note in [60, 64, 67] {
+            processor.process_chord_event(NoteOff { note, velocity: 1.0, voice_id: None, timing: 1, channel: 15 }, &settings);
+        }
+        for note in [67, 71, 74] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 1, channel: 15 }, &settings);
+        }
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 1, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 1, channel: 0 },
+            NoteOn { note: 55, velocity: 1.0, voice_id: None, timing: 1, channel: 0 },
+        ]);
+
+        // the led chord is kept while the chord does not change
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 2, &settings);
+        assert!(send_events.is_empty());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        for note in [60, 64, 67] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &PatternSettings::default());
+        }
+        // chord index 1 (E) seven octaves down is below the MIDI note range
+        processor.process_pattern_event(NoteOn { note: 40, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &PatternSettings { wrap_threshold: 3, ..Default::default() });
+        assert!(send_events.is_empty());
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 1, &PatternSettings { wrap_threshold: 3, note_range: NoteRange { out_of_range: OutOfRange::Fold, ..Default::default() }, ..Default::default() });
+        assert_eq!(*send_events, [NoteOn { note: 4, velocity: 1.0, voice_id: None, timing: 1, channel: 0 }]);
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 2, &PatternSettings { wrap_threshold: 3, note_range: NoteRange { out_of_range: OutOfRange::Clamp, ..Default::default() }, ..Default::default() });
+        assert_eq!(*send_events, [
+            NoteOff { note: 4, velocity: 1.0, voice_id: None, timing: 2, channel: 0 },
+            NoteOn { note: 0, velocity: 1.0, voice_id: None, timing: 2, channel: 0 },
+        ]);
+
+        // a bass range folds the note up into it
+        let bass = NoteRange { low: 36, high: 59, out_of_range: OutOfRange::Fold };
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 3, &PatternSettings { wrap_threshold: 3, note_range: bass, ..Default::default() });
+        assert_eq!(*send_events, [
+            NoteOff { note: 0, velocity: 1.0, voice_id: None, timing: 3, channel: 0 },
+            NoteOn { note: 40, velocity: 1.0, voice_id: None, timing: 3, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_chord_slots() {
+        let mut channel_slots = [0; NUM_CHANNELS];
+        channel_slots[1] = 1;
+        let settings = PatternSettings { chord_channel: 14, chord_channels: 2, channel_slots, wrap_threshold: 3, ..Default::default() };
+        assert_eq!(settings.chord_slot(13), None);
+        assert_eq!(settings.chord_slot(14), Some(0));
+        assert_eq!(settings.chord_slot(15), Some(1));
+
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+        for (channel, chord) in [(14, [48, 52, 55]), (15, [72, 76, 79])] {
+            for note in chord {
+                processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel }, &settings);
+            }
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 1 });
+
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [
+            NoteOn { note: 72, velocity: 1.0, voice_id: None, timing: 0, channel: 1 },
+            NoteOn { note: 48, velocity: 1.0, voice_id: None, timing: 0, channel: 0 },
+        ]);
+
+        // only the keys that follow the slot of the chord change
+        for note in [72, 76, 79] {
+            processor.process_chord_event(NoteOff { note, velocity: 0.0, voice_id: None, timing: 10, channel: 15 }, &settings);
+        }
+        for note in [74, 77, 81] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 10, channel: 15 }, &settings);
+        }
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 10, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 72, velocity: 1.0, voice_id: None, timing: 10, channel: 1 },
+            NoteOn { note: 74, velocity: 1.0, voice_id: None, timing: 10, channel: 1 },
+        ]);
+
+        // the same key on another channel is a different key
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 20, channel: 1 });
+        processor.process_pattern_event(NoteOff { note: 60, velocity: 0.0, voice_id: None, timing: 20, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 20, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 48, velocity: 1.0, voice_id: None, timing: 20, channel: 0 },
+            NoteOff { note: 74, velocity: 1.0, voice_id: None, timing: 20, channel: 1 },
+            NoteOn { note: 74, velocity: 1.0, voice_id: None, timing: 20, channel: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_velocity() {
+        let mut settings = PatternSettings { wrap_threshold: 3, chord_velocity: 1.0, ..Default::default() };
+        settings.velocity_offsets[0] = 0.25;
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        for (note, velocity) in [(60, 0.5), (64, 1.0), (67, 0.25)] {
+            processor.process_chord_event(NoteOn { note, velocity, voice_id: None, timing: 0, channel: 15 }, &settings);
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 0.8, voice_id: None, timing: 0, channel: 0 });
+
+        // the bass is played with the velocity of its chord note and accented
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [
+            NoteOn { note: 64, velocity: 0.8, voice_id: None, timing: 0, channel: 0 },
+            NoteOn { note: 60, velocity: 0.75, voice_id: None, timing: 0, channel: 0 },
+        ]);
+
+        // the release velocity is passed on only if asked for, otherwise the note on velocity is reused
+        processor.process_pattern_event(NoteOff { note: 61, velocity: 0.3, voice_id: None, timing: 5, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 5, &PatternSettings { release_velocity: true, ..settings });
+        assert_eq!(*send_events, [NoteOff { note: 64, velocity: 0.3, voice_id: None, timing: 5, channel: 0 }]);
+
+        processor.process_pattern_event(NoteOff { note: 60, velocity: 0.3, voice_id: None, timing: 6, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 6, &settings);
+        assert_eq!(*send_events, [NoteOff { note: 60, velocity: 0.75, voice_id: None, timing: 6, channel: 0 }]);
+
+        // a chord from the chord memory takes the velocity of its single note
+        let settings = PatternSettings { chord_memory: Some(ChordShape::MAJOR), ..settings };
+        for note in [60, 64, 67] {
+            processor.process_chord_event(NoteOff { note, velocity: 0.0, voice_id: None, timing: 10, channel: 15 }, &settings);
+        }
+        processor.process_chord_event(NoteOn { note: 62, velocity: 0.5, voice_id: None, timing: 10, channel: 15 }, &settings);
+        processor.process_pattern_event(NoteOn { note: 62, velocity: 1.0, voice_id: None, timing: 10, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 10, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 69, velocity: 0.5, voice_id: None, timing: 10, channel: 0 }]);
+    }
+
+    #[test]
+    fn test_velocity_octaves() {
+        let settings = PatternSettings { wrap_threshold: 3, chord_velocity: 1.0, ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        // the octaves of a note have their own velocity
+        for (note, velocity) in [(48, 0.5), (60, 1.0), (64, 0.25)] {
+            processor.process_chord_event(NoteOn { note, velocity, voice_id: None, timing: 0, channel: 15 }, &settings);
+        }
+        processor.process_pattern_event(NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        processor.process_pattern_event(NoteOn { note: 61, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [
+            NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 },
+            NoteOn { note: 48, velocity: 0.5, voice_id: None, timing: 0, channel: 0 },
+        ]);
+
+        // a released octave takes its velocity with it
+        processor.process_chord_event(NoteOff { note: 48, velocity: 0.0, voice_id: None, timing: 5, channel: 15 }, &settings);
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 5, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 48, velocity: 0.5, voice_id: None, timing: 5, channel: 0 },
+            NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+            NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+            NoteOn { note: 64, velocity: 0.25, voice_id: None, timing: 5, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_strum() {
+        let strum = StrumSettings { samples: 10, velocity_ramp: 0.5, ..Default::default() };
+        let settings = PatternSettings { wrap_threshold: 3, strum, ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        for note in [60, 64, 67] {
+            processor.process_chord_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        }
+        for note in [60, 61, 62] {
+            processor.process_pattern_event(NoteOn { note, velocity: 1.0, voice_id: None, timing: 0, channel: 0 });
+        }
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 0, &settings);
+        assert_eq!(*send_events, [NoteOn { note: 60, velocity: 1.0, voice_id: None, timing: 0, channel: 0 }]);
+
+        // a note that has not started yet is not modulated
+        let pressure = PolyPressure { note: 62, pressure: 0.5, voice_id: None, timing: 5, channel: 0 };
+        assert_eq!(processor.modulate_event(&pressure), None);
+
+        let send_events = &mut vec![];
+        processor.send_strum_notes(send_events, 20);
+        assert_eq!(*send_events, [
+            NoteOn { note: 64, velocity: 0.75, voice_id: None, timing: 10, channel: 0 },
+            NoteOn { note: 67, velocity: 0.5, voice_id: None, timing: 20, channel: 0 },
+        ]);
+        assert_eq!(processor.modulate_event(&pressure),
+                   Some(PolyPressure { note: 67, pressure: 0.5, voice_id: None, timing: 5, channel: 0 }));
+
+        // the note offs repeat the ramped velocity
+        for note in [60, 61, 62] {
+            processor.process_pattern_event(NoteOff { note, velocity: 0.0, voice_id: None, timing: 30, channel: 0 });
+        }
+        let send_events = &mut vec![];
+        processor.end_cycle(send_events, 30, &settings);
+        assert_eq!(*send_events, [
+            NoteOff { note: 67, velocity: 0.5, voice_id: None, timing: 30, channel: 0 },
+            NoteOff { note: 64, velocity: 0.75, voice_id: None, timing: 30, channel: 0 },
+            NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 30, channel: 0 },
+        ]);
+    }
+
+    #[test]
+    fn test_chord_memory() {
+        let settings = PatternSettings { wrap_threshold: 3, chord_memory: Some(ChordShape::MAJOR), ..Default::default() };
+        let mut processor = ChordPatternProcessor::<Patterns>::default();
+
+        // a single key plays the remembered chord
+        processor.process_chord_event(NoteOn { note: 62, velocity: 1.0, voice_id: None, timing: 0, channel: 15 }, &settings);
+        assert_eq!(processor.slots[0].chord, Chord::from([62, 66, 69]));
+
+        // a chord that is released note by note keeps its last note on its own
+        processor.process_chord_event(NoteOn { note: 65, velocity: 1.0, voice_id: None, timing: 1, channel: 15 }, &settings);
+        processor.process_chord_event(NoteOn { note: 69, velocity: 1.0, voice_id: None, timing: 1, channel: 15 }, &settings);
+        assert_eq!(processor.slots[0].chord, Chord::from([62, 65, 69]));
+        for note in [69, 65] {
+            processor.process_chord_event(NoteOff { note, velocity: 0.0, voice_id: None, timing: 2, channel: 15 }, &settings);
+        }
+        assert_eq!(processor.slots[0].chord, Chord::from([62]));
+    }
+}
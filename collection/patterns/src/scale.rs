@@ -0,0 +1,171 @@
+use crate::chord::Chord;
+use crate::utils::ScaleType;
+
+/// The pitch classes of a scale, bit 0 is C. Small enough to be derived from the chord on the audio thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scale {
+    pitch_classes: u16,
+}
+
+const MAJOR: u16 = 0b1010_1011_0101;
+const NATURAL_MINOR: u16 = 0b0101_1010_1101;
+
+impl Scale {
+    /// A scale of the given type starting on the pitch class of `key`.
+    pub fn new(scale_type: ScaleType, key: u8) -> Self {
+        let intervals: u16 = match scale_type {
+            ScaleType::Chord | ScaleType::Major => MAJOR,
+            ScaleType::NaturalMinor => NATURAL_MINOR,
+            ScaleType::HarmonicMinor => 0b1001_1010_1101,
+            ScaleType::MelodicMinor => 0b1010_1010_1101,
+            ScaleType::Dorian => 0b0110_1010_1101,
+            ScaleType::Phrygian => 0b0101_1010_1011,
+            ScaleType::Lydian => 0b1010_1101_0101,
+            ScaleType::Mixolydian => 0b0110_1011_0101,
+            ScaleType::Locrian => 0b0101_0110_1011,
+            ScaleType::MajorPentatonic => 0b0010_1001_0101,
+            ScaleType::MinorPentatonic => 0b0100_1010_1001,
+        };
+        Self { pitch_classes: transpose(intervals, key % 12) }
+    }
+
+    /// The scale that is played over the chord. `ScaleType::Chord` derives a major or minor scale from the root of the
+    /// chord, every other type is taken as set. Chord tones that are not part of the scale replace the scale tone
+    /// next to them that does not leave three semitones in a row, so the chord is always contained in the scale.
+    pub fn for_chord(scale_type: ScaleType, key: u8, chord: &Chord) -> Self {
+        let mut scale = match (scale_type, chord.root()) {
+            (ScaleType::Chord, Some(root)) => {
+                let has_interval = |interval: u8| chord.iter().any(|n| (*n + 12 - root % 12) % 12 == interval);
+                if has_interval(3) && !has_interval(4) {
+                    Scale::new(ScaleType::NaturalMinor, root)
+                } else {
+                    Scale::new(ScaleType::Major, root)
+                }
+            }
+            (ScaleType::Chord, None) => Scale::default(),
+            _ => Scale::new(scale_type, key),
+        };
+
+        let mut chord_tones = Scale::default();
+        for note in chord.iter() {
+            chord_tones.insert(*note);
+        }
+
+        for note in chord.iter().copied() {
+            if scale.contains(note) {
+                continue;
+            }
+            let mut altered = scale;
+            altered.insert(note);
+
+            // a chromatic neighbour that is not a chord tone itself is replaced
+            let candidates = [note + 11, note + 1].map(|neighbour| {
+                let mut candidate = altered;
+                candidate.remove(neighbour);
+                (scale.contains(neighbour) && !chord_tones.contains(neighbour)).then_some(candidate)
+            });
+            scale = candidates.iter().flatten().copied().find(|candidate| !candidate.has_chromatic_run())
+                .or_else(|| candidates.iter().flatten().copied().next())
+                .unwrap_or(altered);
+        }
+        scale
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        self.pitch_classes & (1 << (note % 12)) != 0
+    }
+
+    pub fn insert(&mut self, note: u8) {
+        self.pitch_classes |= 1 << (note % 12);
+    }
+
+    pub fn remove(&mut self, note: u8) {
+        self.pitch_classes &= !(1 << (note % 12));
+    }
+
+    /// The number of scale tones per octave.
+    pub fn len(&self) -> usize {
+        self.pitch_classes.count_ones() as usize
+    }
+
+    fn has_chromatic_run(&self) -> bool {
+        self.pitch_classes & transpose(self.pitch_classes, 1) & transpose(self.pitch_classes, 2) != 0
+    }
+
+    /// Writes the scale tones of the octave that starts at `anchor` to `notes`, from the anchor upwards. The anchor is
+    /// always the first note, even if it is not part of the scale.
+    pub fn notes_from(&self, anchor: u8, notes: &mut Chord) {
+        notes.clear();
+        notes.insert(anchor);
+        for note in anchor + 1..(anchor + 12).min(128) {
+            if self.contains(note) {
+                notes.insert(note);
+            }
+        }
+    }
+}
+
+/// Transposes the pitch classes up by `semitones` (0 to 11) by rotating the lowest 12 bits.
+fn transpose(pitch_classes: u16, semitones: u8) -> u16 {
+    ((pitch_classes << semitones) | (pitch_classes >> (12 - semitones))) & 0xfff
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::scale::Scale;
+    use crate::utils::ScaleType;
+
+    fn notes_from(scale: Scale, anchor: u8) -> Vec<u8> {
+        let mut notes = Chord::default();
+        scale.notes_from(anchor, &mut notes);
+        notes.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(notes_from(Scale::new(ScaleType::Major, 0), 60), [60, 62, 64, 65, 67, 69, 71]);
+        assert_eq!(notes_from(Scale::new(ScaleType::Major, 7), 67), [67, 69, 71, 72, 74, 76, 78]);
+        assert_eq!(notes_from(Scale::new(ScaleType::NaturalMinor, 9), 57), [57, 59, 60, 62, 64, 65, 67]);
+        assert_eq!(notes_from(Scale::new(ScaleType::Dorian, 2), 62), [62, 64, 65, 67, 69, 71, 72]);
+        assert_eq!(notes_from(Scale::new(ScaleType::MinorPentatonic, 9), 57), [57, 60, 62, 64, 67]);
+        assert_eq!(Scale::new(ScaleType::HarmonicMinor, 0).len(), 7);
+        assert_eq!(Scale::new(ScaleType::MajorPentatonic, 0).len(), 5);
+    }
+
+    #[test]
+    fn test_for_chord() {
+        // the chord tones are on every other scale degree
+        let c_major = Chord::from([60, 64, 67]);
+        assert_eq!(notes_from(Scale::for_chord(ScaleType::Chord, 0, &c_major), 60), [60, 62, 64, 65, 67, 69, 71]);
+
+        let a_minor = Chord::from([57, 60, 64]);
+        assert_eq!(notes_from(Scale::for_chord(ScaleType::Chord, 0, &a_minor), 57), [57, 59, 60, 62, 64, 65, 67]);
+
+        // the seventh of G7 replaces the major seventh
+        let g_7 = Chord::from([55, 59, 62, 65]);
+        assert_eq!(notes_from(Scale::for_chord(ScaleType::Chord, 0, &g_7), 55), [55, 57, 59, 60, 62, 64, 65]);
+
+        // a chord tone outside of a fixed scale replaces its neighbour: E major in A minor gives A harmonic minor
+        let e_major = Chord::from([52, 56, 59]);
+        assert_eq!(notes_from(Scale::for_chord(ScaleType::NaturalMinor, 9, &e_major), 57),
+                   [57, 59, 60, 62, 64, 65, 68]);
+
+        // Bb major in C major lowers the B
+        let b_flat_major = Chord::from([58, 62, 65]);
+        assert_eq!(notes_from(Scale::for_chord(ScaleType::Major, 0, &b_flat_major), 60),
+                   [60, 62, 64, 65, 67, 69, 70]);
+
+        assert_eq!(Scale::for_chord(ScaleType::Chord, 0, &Chord::default()).len(), 0);
+    }
+
+    #[test]
+    fn test_notes_from_anchor_outside_of_scale() {
+        assert_eq!(notes_from(Scale::new(ScaleType::Major, 0), 61), [61, 62, 64, 65, 67, 69, 71, 72]);
+        assert_eq!(notes_from(Scale::new(ScaleType::Major, 0), 125), [125, 127]);
+    }
+}
@@ -0,0 +1,267 @@
+use nih_plug::midi::NoteEvent::{NoteOn, NoteOff};
+use nih_plug::prelude::*;
+use crate::active_note::NUM_KEYS;
+use crate::utils::StrumDirection;
+
+/// How the notes that start at the same time are spread out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrumSettings {
+    /// the time between two notes of a strum, 0 turns strumming off
+    pub samples: u64,
+    pub direction: StrumDirection,
+    /// how much softer the last note of a strum is than the first one, from 0 to 1
+    pub velocity_ramp: f32,
+    /// the beat the strum starts on, it decides the direction of alternating strums
+    pub beat: u64,
+}
+
+impl Default for StrumSettings {
+    fn default() -> Self {
+        Self {
+            samples: 0,
+            direction: StrumDirection::Up,
+            velocity_ramp: 0.0,
+            beat: 0,
+        }
+    }
+}
+
+/// Holds back the note ons that start at the same time and lets them start one after the other, like the strings of a
+/// strummed guitar. All times are absolute sample positions.
+pub struct Strummer<P: Plugin> {
+    /// the held back note ons and the time they are due
+    pending: Vec<(u64, PluginNoteEvent<P>)>,
+}
+
+impl<P: Plugin> Default for Strummer<P> {
+    fn default() -> Self {
+        Self {
+            // a pattern key has at most one note that is held back
+            pending: Vec::with_capacity(NUM_KEYS),
+        }
+    }
+}
+
+impl<P: Plugin> Strummer<P> {
+    /// Spreads the note ons among `events[start..]`, which all start at `now`. The first note of the strum is left in
+    /// the events, the others are held back until they are due. If no more notes can be held back, the rest start
+    /// right away.
+    pub fn strum(&mut self, events: &mut Vec<PluginNoteEvent<P>>, start: usize, now: u64, settings: &StrumSettings) {
+        self.cancel(events, start);
+        if settings.samples == 0 {
+            return;
+        }
+        drop_instant_notes::<P>(events, start);
+
+        let num_notes = events[start..].iter().filter(|e| matches!(e, NoteOn { .. })).count();
+        if num_notes < 2 {
+            return;
+        }
+
+        let up = match settings.direction {
+            StrumDirection::Up => true,
+            StrumDirection::Down => false,
+            StrumDirection::Alternate => settings.beat.is_multiple_of(2),
+        };
+
+        let mut first = None;
+        let mut held_until = events.len();
+        for idx in start..events.len() {
+            let mut event = events[idx];
+            let (note, velocity) = match &mut event {
+                NoteOn { note, velocity, .. } => (*note, velocity),
+                _ => continue,
+            };
+
+            // notes of the same pitch start in the order they were generated
+            let position = events[start..].iter().enumerate().filter(|(other_idx, other)| match other {
+                NoteOn { note: other_note, .. } => {
+                    let before = if up { *other_note < note } else { *other_note > note };
+                    before || (*other_note == note && start + other_idx < idx)
+                }
+                _ => false,
+            }).count();
+
+            if position == 0 {
+                first = Some(idx);
+                continue;
+            }
+            if self.pending.len() == self.pending.capacity() {
+                held_until = idx;
+                break;
+            }
+
+            let ramp = settings.velocity_ramp * position as f32 / (num_notes - 1) as f32;
+            *velocity = (*velocity * (1.0 - ramp)).clamp(0.0, 1.0);
+            self.pending.push((now + position as u64 * settings.samples, event));
+        }
+
+        let mut idx = 0;
+        events.retain(|e| {
+            let keep = idx < start || idx >= held_until || Some(idx) == first || !matches!(e, NoteOn { .. });
+            idx += 1;
+            keep
+        });
+    }
+
+    /// Drops the note offs among `events[start..]` whose note on is still held back, together with the note on. The
+    /// note has not started yet.
+    pub fn cancel(&mut self, events: &mut Vec<PluginNoteEvent<P>>, start: usize) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let mut idx = 0;
+        events.retain(|e| {
+            let keep = idx < start || match e {
+                NoteOff { note, channel, voice_id, .. } => {
+                    let held_back = self.pending.iter().position(|(_, pending)| matches!(pending,
+                        NoteOn { note: n, channel: c, voice_id: v, .. } if n == note && c == channel && v == voice_id));
+                    match held_back {
+                        Some(pending_idx) => {
+                            self.pending.remove(pending_idx);
+                            false
+                        }
+                        None => true,
+                    }
+                }
+                _ => true,
+            };
+            idx += 1;
+            keep
+        });
+    }
+
+    /// The velocity of the held back note on of a note that has not started yet.
+    pub fn held_back_velocity(&self, note: u8, channel: u8, voice_id: Option<i32>) -> Option<f32> {
+        self.pending.iter().find_map(|(_, pending)| match pending {
+            NoteOn { note: n, channel: c, voice_id: v, velocity, .. } if *n == note && *c == channel && *v == voice_id => {
+                Some(*velocity)
+            }
+            _ => None,
+        })
+    }
+
+    /// The time the next held back note is due.
+    pub fn next_due(&self) -> Option<u64> {
+        self.pending.iter().map(|(due, _)| *due).min()
+    }
+
+    /// Takes the held back note that is due first, if it is due by `now`.
+    pub fn pop_due(&mut self, now: u64) -> Option<(u64, PluginNoteEvent<P>)> {
+        let idx = self.pending.iter().enumerate()
+            .filter(|(_, (due, _))| *due <= now)
+            .min_by_key(|(_, (due, _))| *due)
+            .map(|(idx, _)| idx)?;
+        Some(self.pending.remove(idx))
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Drops the note ons among `events[start..]` that a later note off among them ends right away, together with the
+/// note off. They would not be heard, and a held back note on must not start after its note off.
+fn drop_instant_notes<P: Plugin>(events: &mut Vec<PluginNoteEvent<P>>, start: usize) {
+    let mut idx = start;
+    while idx < events.len() {
+        let note_off = match events[idx] {
+            NoteOn { note, channel, voice_id, .. } => events[idx + 1..].iter().position(|e| matches!(e,
+                NoteOff { note: n, channel: c, voice_id: v, .. } if *n == note && *c == channel && *v == voice_id)),
+            _ => None,
+        };
+        match note_off {
+            Some(offset) => {
+                events.remove(idx + 1 + offset);
+                events.remove(idx);
+            }
+            None => idx += 1,
+        }
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::midi::PluginNoteEvent;
+    use nih_plug::midi::NoteEvent::{NoteOn, NoteOff};
+    use crate::Patterns;
+    use crate::strum::{Strummer, StrumSettings};
+    use crate::utils::StrumDirection;
+
+    fn note_on(note: u8, velocity: f32) -> PluginNoteEvent<Patterns> {
+        NoteOn { note, velocity, voice_id: None, timing: 0, channel: 0 }
+    }
+
+    fn drain(strummer: &mut Strummer<Patterns>) -> Vec<(u64, PluginNoteEvent<Patterns>)> {
+        std::iter::from_fn(|| strummer.pop_due(u64::MAX)).collect()
+    }
+
+    #[test]
+    fn test_strum() {
+        let settings = StrumSettings { samples: 10, ..Default::default() };
+        let mut strummer = Strummer::<Patterns>::default();
+
+        // the lowest note starts right away, the others follow upwards
+        let mut events = vec![note_on(67, 1.0), note_on(60, 1.0), note_on(64, 1.0)];
+        strummer.strum(&mut events, 0, 100, &settings);
+        assert_eq!(events, [note_on(60, 1.0)]);
+        assert_eq!(strummer.next_due(), Some(110));
+        assert_eq!(strummer.pop_due(109), None);
+        assert_eq!(drain(&mut strummer), [(110, note_on(64, 1.0)), (120, note_on(67, 1.0))]);
+
+        // down on the second beat of alternating strums, with a velocity ramp
+        let settings = StrumSettings { direction: StrumDirection::Alternate, velocity_ramp: 0.5, beat: 1, ..settings };
+        let mut events = vec![note_on(60, 1.0), note_on(64, 1.0), note_on(67, 1.0)];
+        strummer.strum(&mut events, 0, 200, &settings);
+        assert_eq!(events, [note_on(67, 1.0)]);
+        assert_eq!(drain(&mut strummer), [(210, note_on(64, 0.75)), (220, note_on(60, 0.5))]);
+
+        // a single note is not strummed
+        let mut events = vec![note_on(60, 1.0)];
+        strummer.strum(&mut events, 0, 300, &settings);
+        assert_eq!(events, [note_on(60, 1.0)]);
+        assert_eq!(strummer.next_due(), None);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let settings = StrumSettings { samples: 10, ..Default::default() };
+        let mut strummer = Strummer::<Patterns>::default();
+
+        let mut events = vec![note_on(60, 1.0), note_on(64, 1.0)];
+        strummer.strum(&mut events, 0, 0, &settings);
+
+        // a note that has not started does not end either
+        let mut events = vec![
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+            NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 5, channel: 0 },
+        ];
+        strummer.strum(&mut events, 0, 5, &settings);
+        assert_eq!(events, [NoteOff { note: 60, velocity: 1.0, voice_id: None, timing: 5, channel: 0 }]);
+        assert_eq!(strummer.next_due(), None);
+    }
+
+    #[test]
+    fn test_instant_notes() {
+        let settings = StrumSettings { samples: 10, velocity_ramp: 0.5, ..Default::default() };
+        let mut strummer = Strummer::<Patterns>::default();
+
+        // a note that ends as soon as it starts is dropped, the note pressed again is strummed
+        let mut events = vec![
+            note_on(60, 1.0),
+            note_on(64, 1.0),
+            NoteOff { note: 64, velocity: 1.0, voice_id: None, timing: 0, channel: 0 },
+            note_on(64, 1.0),
+        ];
+        strummer.strum(&mut events, 0, 0, &settings);
+        assert_eq!(events, [note_on(60, 1.0)]);
+        assert_eq!(strummer.held_back_velocity(64, 0, None), Some(0.5));
+        assert_eq!(strummer.held_back_velocity(64, 1, None), None);
+        assert_eq!(drain(&mut strummer), [(10, note_on(64, 0.5))]);
+    }
+}
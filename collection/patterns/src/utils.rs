@@ -2,22 +2,206 @@ use nih_plug::midi::PluginNoteEvent;
 use crate::processors::PatternChordData;
 use nih_plug::prelude::*;
 
-#[derive(Enum, PartialEq)]
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
 pub enum KeyboardMode {
     AllKeys = 0,
     IgnoreBlackKeys = 1,
-    //ShiftBlackKeysRight = 2, //Not implemented
-    //ShiftBlackKeysLeft = 3
+    ShiftBlackKeysRight = 2,
+    ShiftBlackKeysLeft = 3
 }
 
-pub fn note_to_chord_idx_octave(note: u8, wrap_threshold: u8) -> (u8, i8) {
+/// What happens to a sounding pattern note when the chord changes underneath it.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum ChordChangeMode {
+    /// Release the old note and press the new one.
+    Retrigger = 0,
+    /// Press the new note before releasing the old one.
+    Legato = 1,
+    /// Keep the old note until the pattern key is released.
+    Hold = 2,
+    /// Release the old note without pressing a new one.
+    Release = 3,
+}
+
+/// The order in which the chord notes are assigned to the chord indices.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum ChordOrder {
+    /// From the lowest to the highest note.
+    Ascending = 0,
+    /// From the highest to the lowest note.
+    Descending = 1,
+    /// In the order the chord notes were pressed.
+    Arrival = 2,
+    /// The detected root of the chord, then the other notes ascending.
+    #[name = "Root First"]
+    RootFirst = 3,
+    /// The lowest note, then the other notes in the order they were pressed. Only the root key plays the lowest note,
+    /// the other keys wrap around the notes above it.
+    #[name = "Bass Separated"]
+    BassSeparated = 4,
+}
+
+/// How a pattern key is mapped to the note it plays.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum PatternMode {
+    /// The pattern keys pick the chord notes.
+    Chord = 0,
+    /// The pattern keys walk the degrees of a scale that starts on the first chord note.
+    Scale = 1,
+}
+
+/// The scale of the scale pattern mode.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum ScaleType {
+    /// A major or minor scale on the root of the chord.
+    #[name = "From Chord"]
+    Chord = 0,
+    Major = 1,
+    #[name = "Natural Minor"]
+    NaturalMinor = 2,
+    #[name = "Harmonic Minor"]
+    HarmonicMinor = 3,
+    #[name = "Melodic Minor"]
+    MelodicMinor = 4,
+    Dorian = 5,
+    Phrygian = 6,
+    Lydian = 7,
+    Mixolydian = 8,
+    Locrian = 9,
+    #[name = "Major Pentatonic"]
+    MajorPentatonic = 10,
+    #[name = "Minor Pentatonic"]
+    MinorPentatonic = 11,
+}
+
+/// The key of a fixed scale.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum ScaleKey {
+    C = 0,
+    #[name = "C#"]
+    CSharp = 1,
+    D = 2,
+    #[name = "D#"]
+    DSharp = 3,
+    E = 4,
+    F = 5,
+    #[name = "F#"]
+    FSharp = 6,
+    G = 7,
+    #[name = "G#"]
+    GSharp = 8,
+    A = 9,
+    #[name = "A#"]
+    ASharp = 10,
+    B = 11,
+}
+
+/// Which voice of the chord, counted from the top, is moved down an octave.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum VoicingDrop {
+    None = 0,
+    #[name = "Drop 2"]
+    Drop2 = 1,
+    #[name = "Drop 3"]
+    Drop3 = 2,
+}
+
+/// What happens to a pattern note whose pitch is outside of the note range.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum OutOfRange {
+    /// The pattern key stays silent.
+    Drop = 0,
+    /// The note is moved by octaves into the range.
+    Fold = 1,
+    /// The note is moved to the nearest note of the range.
+    Clamp = 2,
+}
+
+/// How chord symbols arrive on the chord channel, besides the chord notes.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum ChordSymbolMode {
+    /// Only chord notes.
+    Off = 0,
+    /// The root note and the chord quality as the values of two CCs.
+    #[name = "CC Pair"]
+    ControlChange = 1,
+    /// The root note as the value of a CC, the chord quality as a program change.
+    #[name = "Program Change"]
+    ProgramChange = 2,
+}
+
+/// The notes the pattern keys may play.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteRange {
+    /// the lowest and highest note, in either order
+    pub low: u8,
+    pub high: u8,
+    pub out_of_range: OutOfRange,
+}
+
+impl Default for NoteRange {
+    fn default() -> Self {
+        Self {
+            low: 0,
+            high: 127,
+            out_of_range: OutOfRange::Drop,
+        }
+    }
+}
+
+impl NoteRange {
+    /// Turns a pitch into a note of the range. Pitches outside of it are dropped, folded or clamped. Folding drops a
+    /// pitch that has no octave in a range smaller than an octave.
+    pub fn apply(&self, pitch: i32) -> Option<u8> {
+        let low = self.low.min(self.high) as i32;
+        let high = self.low.max(self.high) as i32;
+
+        let note = match self.out_of_range {
+            // the lowest or highest note of the same pitch class
+            OutOfRange::Fold if pitch < low => low + (pitch - low).rem_euclid(12),
+            OutOfRange::Fold if pitch > high => high - (high - pitch).rem_euclid(12),
+            OutOfRange::Drop | OutOfRange::Fold => pitch,
+            OutOfRange::Clamp => pitch.clamp(low, high),
+        };
+        if (low..=high).contains(&note) { Some(note as u8) } else { None }
+    }
+}
+
+/// Unit of time parameters that can follow the tempo of the host.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum TimeUnit {
+    #[name = "ms"]
+    Milliseconds = 0,
+    Beats = 1,
+}
+
+/// The order in which the notes of a strum start.
+#[derive(Enum, PartialEq, Debug, Clone, Copy)]
+pub enum StrumDirection {
+    /// From the lowest to the highest note.
+    Up = 0,
+    /// From the highest to the lowest note.
+    Down = 1,
+    /// Up on every other beat starting with the first, down in between.
+    Alternate = 2,
+}
+
+pub fn time_to_samples(time: f32, time_unit: TimeUnit, tempo: f64, sample_rate: f32) -> u64 {
+    let seconds = match time_unit {
+        TimeUnit::Milliseconds => time as f64 / 1000.0,
+        TimeUnit::Beats => time as f64 * 60.0 / tempo.max(0.00001),
+    };
+    (seconds * sample_rate as f64).round().max(0.0) as u64
+}
+
+pub fn note_to_chord_idx_octave(note: u8, wrap_threshold: u8, root_note: u8) -> (u8, i32) {
     (
         //note
-        ((note as i32 - 60).rem_euclid(
+        ((note as i32 - root_note as i32).rem_euclid(
             wrap_threshold as i32)) as u8,
         //octave
-        ((note as i32 - 60).div_euclid(
-            wrap_threshold as i32)) as i8,
+        (note as i32 - root_note as i32).div_euclid(
+            wrap_threshold as i32),
     )
 }
 
@@ -25,12 +209,12 @@ pub fn count_black_keys(note: u8) -> u8 {
     // Calculate the number of octaves between the lowest and the highest note
     let octaves = ((note) / 12) as u8;
     // Return the number of black keys in the octaves
-    return (octaves * 5 + [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 5][(note.rem_euclid(12)) as usize]) as u8;
+    return (octaves * 5 + [0, 1, 1, 2, 2, 2, 3, 3, 4, 4, 5, 5][(note.rem_euclid(12)) as usize]) as u8;
 }
 
-pub fn count_black_keys_from_c3(note: u8) -> i32 {
-  // there are 25 black keys from C0 (0) to C3 (60)
-  count_black_keys(note) as i32 - 25
+pub fn count_black_keys_from_root(note: u8, root_note: u8) -> i32 {
+  // black keys below the root are not counted. A black root itself is skipped, so the next white key becomes the root.
+  count_black_keys(note) as i32 - (count_black_keys(root_note) as i32 - is_black_key(root_note) as i32)
 }
 
 pub fn is_black_key(note: u8) -> bool {
@@ -44,19 +228,35 @@ pub fn is_black_key(note: u8) -> bool {
     }
 }
 
-pub fn raw_note_apply_keyboard_mode(raw_note: u8, keyboard_mode: &KeyboardMode)-> Option<u8> {
+fn white_key_apply_root(white_note: u8, root_note: u8) -> Option<u8> {
+    u8::try_from(white_note as i32 - count_black_keys_from_root(white_note, root_note)).ok()
+}
+
+pub fn raw_note_apply_keyboard_mode(raw_note: u8, keyboard_mode: &KeyboardMode, root_note: u8)-> Option<u8> {
     match keyboard_mode {
         KeyboardMode::IgnoreBlackKeys =>
           match is_black_key(raw_note) {
               true=> None,
-              false => u8::try_from(raw_note as i32 - count_black_keys_from_c3(raw_note)).ok()
+              false => white_key_apply_root(raw_note, root_note)
+          }
+        // black keys are never at the edges of the MIDI range, so their neighbours always exist
+        KeyboardMode::ShiftBlackKeysRight =>
+          match is_black_key(raw_note) {
+              true=> white_key_apply_root(raw_note + 1, root_note),
+              false => white_key_apply_root(raw_note, root_note)
+          }
+        KeyboardMode::ShiftBlackKeysLeft =>
+          match is_black_key(raw_note) {
+              true=> white_key_apply_root(raw_note - 1, root_note),
+              false => white_key_apply_root(raw_note, root_note)
           }
         KeyboardMode::AllKeys =>  Some(raw_note),
     }
 }
 
-pub fn get_chord_data(chord_vec: &Vec<u8>, note_value: u8, wrap_threshold: u8, octave_range: u8) -> PatternChordData {
-    let (chord_idx, octave) = note_to_chord_idx_octave(note_value, wrap_threshold);
+pub fn get_chord_data(chord_vec: &[u8], note_value: u8, wrap_threshold: u8, octave_range: u8, root_note: u8,
+                      note_range: NoteRange) -> PatternChordData {
+    let (chord_idx, octave) = note_to_chord_idx_octave(note_value, wrap_threshold, root_note);
 
     //let chord_vec: Vec<u8> = self.chord.iter().cloned().collect();
 
@@ -67,12 +267,36 @@ pub fn get_chord_data(chord_vec: &Vec<u8>, note_value: u8, wrap_threshold: u8, o
     };
 
     if let Some(note) = chord_vec.get(chord_idx as usize) {
-        chord_data.triggered_note = u8::try_from(*note as i8 + octave_range as i8 * octave).ok();
+        chord_data.triggered_note = note_range.apply(*note as i32 + octave_range as i32 * octave);
     };
 
     chord_data
 }
 
+/// Like `get_chord_data`, but the first chord note is the bass. Only the root key plays it and it is never moved by
+/// octaves. The keys above the root wrap around the other notes starting with chord index 1, the keys below it
+/// starting with the last one.
+pub fn get_bass_separated_chord_data(chord_vec: &[u8], note_value: u8, wrap_threshold: u8, octave_range: u8,
+                                     root_note: u8, note_range: NoteRange) -> PatternChordData {
+    let offset = note_value as i32 - root_note as i32;
+    if offset == 0 || wrap_threshold < 2 {
+        // no notes above the bass to wrap around
+        return get_chord_data(chord_vec, note_value, wrap_threshold, octave_range, root_note, note_range);
+    }
+
+    let upper_offset = if offset > 0 { offset - 1 } else { offset };
+    let upper_notes = wrap_threshold as i32 - 1;
+    let chord_idx = 1 + upper_offset.rem_euclid(upper_notes) as u8;
+    let octave = upper_offset.div_euclid(upper_notes);
+
+    PatternChordData {
+        chord_idx,
+        octave,
+        triggered_note: chord_vec.get(chord_idx as usize)
+            .and_then(|note| note_range.apply(*note as i32 + octave_range as i32 * octave)),
+    }
+}
+
 pub fn get_note_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNoteEvent<P>) -> Option<u8> {
     match note_event {
         PluginNoteEvent::<P>::NoteOn { note, .. }
@@ -131,6 +355,29 @@ pub fn get_channel_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNot
     }
 }
 
+/// MIDI CC 64, the sustain pedal.
+pub const SUSTAIN_PEDAL: u8 = 64;
+
+/// Whether the event presses (`Some(true)`) or releases (`Some(false)`) the sustain pedal.
+pub fn get_sustain_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNoteEvent<P>) -> Option<bool> {
+    match note_event {
+        PluginNoteEvent::<P>::MidiCC { cc: SUSTAIN_PEDAL, value, .. } => Some(*value >= 0.5),
+        _ => None,
+    }
+}
+
+/// The channel of every event that has one, including the channel messages that have no note.
+pub fn get_channel_of_message<P: nih_plug::prelude::Plugin>(note_event: &PluginNoteEvent<P>) -> Option<u8> {
+    match note_event {
+        PluginNoteEvent::<P>::VoiceTerminated { channel, .. }
+        | PluginNoteEvent::<P>::MidiChannelPressure { channel, .. }
+        | PluginNoteEvent::<P>::MidiPitchBend { channel, .. }
+        | PluginNoteEvent::<P>::MidiCC { channel, .. }
+        | PluginNoteEvent::<P>::MidiProgramChange { channel, .. } => Some(*channel),
+        _ => get_channel_of_event::<P>(note_event),
+    }
+}
+
 pub fn get_voice_id_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNoteEvent<P>) -> Option<i32> { // Check if correct events are selected
     match note_event {
         PluginNoteEvent::<P>::NoteOn { voice_id, .. }
@@ -150,39 +397,39 @@ pub fn get_voice_id_of_event<P: nih_plug::prelude::Plugin>(note_event: &PluginNo
 
 #[cfg(test)]
 mod tests {
-    use crate::utils::{get_channel_of_event, get_chord_data, note_to_chord_idx_octave, is_black_key, count_black_keys_from_c3, raw_note_apply_keyboard_mode, KeyboardMode};
+    use crate::utils::{time_to_samples, TimeUnit, get_channel_of_event, get_bass_separated_chord_data, get_chord_data, note_to_chord_idx_octave, is_black_key, count_black_keys_from_root, raw_note_apply_keyboard_mode, KeyboardMode, NoteRange, OutOfRange};
     use nih_plug::midi::PluginNoteEvent;
     use crate::Patterns;
     use crate::processors::PatternChordData;
 
     #[test]
     fn test_count_black_keys() {
-        assert_eq!(count_black_keys_from_c3(50), -4);
-        assert_eq!(count_black_keys_from_c3(51), -3);
-        assert_eq!(count_black_keys_from_c3(52), -3);
-        assert_eq!(count_black_keys_from_c3(53), -2);
-        assert_eq!(count_black_keys_from_c3(54), -2);
-        assert_eq!(count_black_keys_from_c3(55), -1);
-        assert_eq!(count_black_keys_from_c3(56), -1);
-        assert_eq!(count_black_keys_from_c3(57), 0);
-        assert_eq!(count_black_keys_from_c3(58), 0);
-        assert_eq!(count_black_keys_from_c3(59), 0);
-        assert_eq!(count_black_keys_from_c3(60), 0);
-        assert_eq!(count_black_keys_from_c3(61), 1);
-        assert_eq!(count_black_keys_from_c3(62), 1);
-        assert_eq!(count_black_keys_from_c3(63), 2);
-        assert_eq!(count_black_keys_from_c3(64), 2);
-        assert_eq!(count_black_keys_from_c3(65), 3);
-        assert_eq!(count_black_keys_from_c3(66), 3);
-        assert_eq!(count_black_keys_from_c3(67), 4);
-        assert_eq!(count_black_keys_from_c3(68), 4);
-        assert_eq!(count_black_keys_from_c3(69), 5);
-        assert_eq!(count_black_keys_from_c3(70), 5);
-        assert_eq!(count_black_keys_from_c3(71), 5);
-        assert_eq!(count_black_keys_from_c3(72), 5);
-        assert_eq!(count_black_keys_from_c3(73), 6);
-        assert_eq!(count_black_keys_from_c3(74), 6);
-        assert_eq!(count_black_keys_from_c3(75), 7);
+        assert_eq!(count_black_keys_from_root(50, 60), -4);
+        assert_eq!(count_black_keys_from_root(51, 60), -3);
+        assert_eq!(count_black_keys_from_root(52, 60), -3);
+        assert_eq!(count_black_keys_from_root(53, 60), -3);
+        assert_eq!(count_black_keys_from_root(54, 60), -2);
+        assert_eq!(count_black_keys_from_root(55, 60), -2);
+        assert_eq!(count_black_keys_from_root(56, 60), -1);
+        assert_eq!(count_black_keys_from_root(57, 60), -1);
+        assert_eq!(count_black_keys_from_root(58, 60), 0);
+        assert_eq!(count_black_keys_from_root(59, 60), 0);
+        assert_eq!(count_black_keys_from_root(60, 60), 0);
+        assert_eq!(count_black_keys_from_root(61, 60), 1);
+        assert_eq!(count_black_keys_from_root(62, 60), 1);
+        assert_eq!(count_black_keys_from_root(63, 60), 2);
+        assert_eq!(count_black_keys_from_root(64, 60), 2);
+        assert_eq!(count_black_keys_from_root(65, 60), 2);
+        assert_eq!(count_black_keys_from_root(66, 60), 3);
+        assert_eq!(count_black_keys_from_root(67, 60), 3);
+        assert_eq!(count_black_keys_from_root(68, 60), 4);
+        assert_eq!(count_black_keys_from_root(69, 60), 4);
+        assert_eq!(count_black_keys_from_root(70, 60), 5);
+        assert_eq!(count_black_keys_from_root(71, 60), 5);
+        assert_eq!(count_black_keys_from_root(72, 60), 5);
+        assert_eq!(count_black_keys_from_root(73, 60), 6);
+        assert_eq!(count_black_keys_from_root(74, 60), 6);
+        assert_eq!(count_black_keys_from_root(75, 60), 7);
     }    
 
     #[test]
@@ -195,15 +442,24 @@ mod tests {
         assert_eq!(note_channel, None);
     }
 
+    #[test]
+    fn test_time_to_samples() {
+        assert_eq!(time_to_samples(0.0, TimeUnit::Milliseconds, 120.0, 44_100.0), 0);
+        assert_eq!(time_to_samples(10.0, TimeUnit::Milliseconds, 120.0, 44_100.0), 441);
+        assert_eq!(time_to_samples(1.0, TimeUnit::Beats, 120.0, 44_100.0), 22_050);
+        assert_eq!(time_to_samples(0.25, TimeUnit::Beats, 60.0, 48_000.0), 12_000);
+        assert_eq!(time_to_samples(1.0, TimeUnit::Beats, 0.0, 0.0), 0); // no crash on 0
+    }
+
     #[test]
     fn test_note_to_chord_idx_octave() {
-        let (note_index, octave) = note_to_chord_idx_octave(60, 3);
+        let (note_index, octave) = note_to_chord_idx_octave(60, 3, 60);
         assert_eq!((note_index, octave), (0, 0));
-        let (note_index, octave) = note_to_chord_idx_octave(61, 3);
+        let (note_index, octave) = note_to_chord_idx_octave(61, 3, 60);
         assert_eq!((note_index, octave), (1, 0));
-        let (note_index, octave) = note_to_chord_idx_octave(62, 3);
+        let (note_index, octave) = note_to_chord_idx_octave(62, 3, 60);
         assert_eq!((note_index, octave), (2, 0));
-        let (note_index, octave) = note_to_chord_idx_octave(63, 3);
+        let (note_index, octave) = note_to_chord_idx_octave(63, 3, 60);
         assert_eq!((note_index, octave), (0, 1));
     }
 
@@ -226,14 +482,45 @@ mod tests {
 
     #[test]
     fn test_raw_note_apply_keyboard_mode(){
-        assert_eq!(raw_note_apply_keyboard_mode(60, &KeyboardMode::AllKeys), Some(60));
-        assert_eq!(raw_note_apply_keyboard_mode(60, &KeyboardMode::IgnoreBlackKeys), Some(60));
-        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::AllKeys), Some(61));
-        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::IgnoreBlackKeys), None);
-        assert_eq!(raw_note_apply_keyboard_mode(62, &KeyboardMode::AllKeys), Some(62));
-        assert_eq!(raw_note_apply_keyboard_mode(62, &KeyboardMode::IgnoreBlackKeys), Some(61));
-        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::AllKeys), Some(63));
-        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::IgnoreBlackKeys), None);
+        assert_eq!(raw_note_apply_keyboard_mode(60, &KeyboardMode::AllKeys, 60), Some(60));
+        assert_eq!(raw_note_apply_keyboard_mode(60, &KeyboardMode::IgnoreBlackKeys, 60), Some(60));
+        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::AllKeys, 60), Some(61));
+        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::IgnoreBlackKeys, 60), None);
+        assert_eq!(raw_note_apply_keyboard_mode(62, &KeyboardMode::AllKeys, 60), Some(62));
+        assert_eq!(raw_note_apply_keyboard_mode(62, &KeyboardMode::IgnoreBlackKeys, 60), Some(61));
+        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::AllKeys, 60), Some(63));
+        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::IgnoreBlackKeys, 60), None);
+        // E3 and F3 are neighbouring white keys
+        assert_eq!(raw_note_apply_keyboard_mode(64, &KeyboardMode::IgnoreBlackKeys, 60), Some(62));
+        assert_eq!(raw_note_apply_keyboard_mode(65, &KeyboardMode::IgnoreBlackKeys, 60), Some(63));
+    }
+
+    #[test]
+    fn test_raw_note_apply_keyboard_mode_shift_black_keys(){
+        // white keys are counted like in IgnoreBlackKeys
+        for note in [59, 60, 62, 64, 65, 67, 69, 71, 72] {
+            let white_note = raw_note_apply_keyboard_mode(note, &KeyboardMode::IgnoreBlackKeys, 60);
+            assert_eq!(raw_note_apply_keyboard_mode(note, &KeyboardMode::ShiftBlackKeysRight, 60), white_note);
+            assert_eq!(raw_note_apply_keyboard_mode(note, &KeyboardMode::ShiftBlackKeysLeft, 60), white_note);
+        }
+
+        // black keys fold onto the white key above
+        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::ShiftBlackKeysRight, 60), Some(61)); // C# -> D
+        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::ShiftBlackKeysRight, 60), Some(62)); // D# -> E
+        assert_eq!(raw_note_apply_keyboard_mode(66, &KeyboardMode::ShiftBlackKeysRight, 60), Some(64)); // F# -> G
+        assert_eq!(raw_note_apply_keyboard_mode(70, &KeyboardMode::ShiftBlackKeysRight, 60), Some(66)); // A# -> B
+        assert_eq!(raw_note_apply_keyboard_mode(58, &KeyboardMode::ShiftBlackKeysRight, 60), Some(59)); // A#2 -> B2
+
+        // black keys fold onto the white key below
+        assert_eq!(raw_note_apply_keyboard_mode(61, &KeyboardMode::ShiftBlackKeysLeft, 60), Some(60)); // C# -> C
+        assert_eq!(raw_note_apply_keyboard_mode(63, &KeyboardMode::ShiftBlackKeysLeft, 60), Some(61)); // D# -> D
+        assert_eq!(raw_note_apply_keyboard_mode(66, &KeyboardMode::ShiftBlackKeysLeft, 60), Some(63)); // F# -> F
+        assert_eq!(raw_note_apply_keyboard_mode(70, &KeyboardMode::ShiftBlackKeysLeft, 60), Some(65)); // A# -> A
+        assert_eq!(raw_note_apply_keyboard_mode(58, &KeyboardMode::ShiftBlackKeysLeft, 60), Some(58)); // A#2 -> A2
+
+        // the root note is respected
+        assert_eq!(raw_note_apply_keyboard_mode(37, &KeyboardMode::ShiftBlackKeysRight, 36), Some(37));
+        assert_eq!(raw_note_apply_keyboard_mode(37, &KeyboardMode::ShiftBlackKeysLeft, 36), Some(36));
     }
 
     #[test]
@@ -241,28 +528,28 @@ mod tests {
         let chord = vec![72, 74, 76];
 
         // positive octave
-        let data = get_chord_data(&chord, 60, 3, 12);
+        let data = get_chord_data(&chord, 60, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 0,
             chord_idx: 0,
             triggered_note: Some(72),
         }, data);
 
-        let data = get_chord_data(&chord, 61, 3, 12);
+        let data = get_chord_data(&chord, 61, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 0,
             chord_idx: 1,
             triggered_note: Some(74),
         }, data);
 
-        let data = get_chord_data(&chord, 62, 3, 12);
+        let data = get_chord_data(&chord, 62, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 0,
             chord_idx: 2,
             triggered_note: Some(76),
         }, data);
 
-        let data = get_chord_data(&chord, 63, 3, 12);
+        let data = get_chord_data(&chord, 63, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 1,
             chord_idx: 0,
@@ -270,7 +557,7 @@ mod tests {
         }, data);
 
         // invalid chord idx -> no note triggered
-        let data = get_chord_data(&chord, 63, 4, 12);
+        let data = get_chord_data(&chord, 63, 4, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 0,
             chord_idx: 3,
@@ -278,21 +565,21 @@ mod tests {
         }, data);
 
         // negative octave
-        let data = get_chord_data(&chord, 59, 3, 12);
+        let data = get_chord_data(&chord, 59, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: -1,
             chord_idx: 2,
             triggered_note: Some(64),
         }, data);
 
-        let data = get_chord_data(&chord, 58, 3, 12);
+        let data = get_chord_data(&chord, 58, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: -1,
             chord_idx: 1,
             triggered_note: Some(62),
         }, data);
 
-        let data = get_chord_data(&chord, 57, 3, 12);
+        let data = get_chord_data(&chord, 57, 3, 12, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: -1,
             chord_idx: 0,
@@ -300,25 +587,223 @@ mod tests {
         }, data);
 
         // octave range
-        let data = get_chord_data(&chord, 61, 1, 24);
+        let data = get_chord_data(&chord, 61, 1, 24, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 1,
             chord_idx: 0,
             triggered_note: Some(96),
         }, data);
 
-        let data = get_chord_data(&chord, 61, 1, 6);
+        let data = get_chord_data(&chord, 61, 1, 6, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 1,
             chord_idx: 0,
             triggered_note: Some(78),
         }, data);
 
-        let data = get_chord_data(&chord, 61, 1, 1);
+        let data = get_chord_data(&chord, 61, 1, 1, 60, NoteRange::default());
         assert_eq!(PatternChordData {
             octave: 1,
             chord_idx: 0,
             triggered_note: Some(73),
         }, data);
     }
+
+    #[test]
+    fn test_root_note() {
+        let ignore_black_keys = |note: u8, root_note: u8| {
+            raw_note_apply_keyboard_mode(note, &KeyboardMode::IgnoreBlackKeys, root_note)
+                .map(|white_note| note_to_chord_idx_octave(white_note, 3, root_note))
+        };
+
+        // C in every octave
+        for root_note in (0..=108).step_by(12) {
+            // all keys are counted from the root
+            assert_eq!(note_to_chord_idx_octave(root_note, 3, root_note), (0, 0));
+            assert_eq!(note_to_chord_idx_octave(root_note + 1, 3, root_note), (1, 0));
+            assert_eq!(note_to_chord_idx_octave(root_note + 3, 3, root_note), (0, 1));
+            assert_eq!(raw_note_apply_keyboard_mode(root_note + 1, &KeyboardMode::AllKeys, root_note), Some(root_note + 1));
+
+            // white keys are counted from the root
+            assert_eq!(ignore_black_keys(root_note, root_note), Some((0, 0)));
+            assert_eq!(ignore_black_keys(root_note + 1, root_note), None); // C#
+            assert_eq!(ignore_black_keys(root_note + 2, root_note), Some((1, 0))); // D
+            assert_eq!(ignore_black_keys(root_note + 4, root_note), Some((2, 0))); // E
+            assert_eq!(ignore_black_keys(root_note + 5, root_note), Some((0, 1))); // F
+            assert_eq!(ignore_black_keys(root_note + 12, root_note), Some((1, 2))); // 7 white keys above
+
+            if root_note > 0 {
+                assert_eq!(note_to_chord_idx_octave(root_note - 1, 3, root_note), (2, -1));
+                assert_eq!(ignore_black_keys(root_note - 1, root_note), Some((2, -1))); // B
+                assert_eq!(ignore_black_keys(root_note - 3, root_note), Some((1, -1))); // A
+            }
+        }
+
+        // any other root
+        for root_note in 0..=115 {
+            assert_eq!(note_to_chord_idx_octave(root_note + 12, 12, root_note), (0, 1));
+        }
+
+        // white root that is not a C
+        assert_eq!(ignore_black_keys(64, 64), Some((0, 0))); // E3
+        assert_eq!(ignore_black_keys(65, 64), Some((1, 0))); // F3
+        assert_eq!(ignore_black_keys(67, 64), Some((2, 0))); // G3
+
+        // a black root moves the origin to the next white key
+        assert_eq!(ignore_black_keys(61, 61), None);
+        assert_eq!(ignore_black_keys(62, 61), Some((0, 0)));
+        assert_eq!(ignore_black_keys(60, 61), Some((2, -1)));
+    }
+
+    #[test]
+    fn test_get_bass_separated_chord_data() {
+        let chord = vec![48, 64, 67, 72];
+        let chord_data = |note| {
+            let data = get_bass_separated_chord_data(&chord, note, 4, 12, 60, NoteRange::default());
+            (data.chord_idx, data.octave, data.triggered_note)
+        };
+
+        // the bass is only played by the root key, the keys above it wrap around the other notes
+        assert_eq!(chord_data(60), (0, 0, Some(48)));
+        assert_eq!(chord_data(61), (1, 0, Some(64)));
+        assert_eq!(chord_data(63), (3, 0, Some(72)));
+        assert_eq!(chord_data(64), (1, 1, Some(76)));
+        assert_eq!(chord_data(66), (3, 1, Some(84)));
+        assert_eq!(chord_data(67), (1, 2, Some(88)));
+
+        // the keys below the root count down from the highest note
+        assert_eq!(chord_data(59), (3, -1, Some(60)));
+        assert_eq!(chord_data(57), (1, -1, Some(52)));
+        assert_eq!(chord_data(56), (3, -2, Some(48)));
+
+        // without notes above the bass it wraps like every other order
+        let data = get_bass_separated_chord_data(&chord, 61, 1, 12, 60, NoteRange::default());
+        assert_eq!((data.chord_idx, data.octave), (0, 1));
+    }
+
+    #[test]
+    fn test_get_chord_data_root_note() {
+        let chord = vec![72, 74, 76];
+
+        let data = get_chord_data(&chord, 36, 3, 12, 36, NoteRange::default());
+        assert_eq!(PatternChordData {
+            octave: 0,
+            chord_idx: 0,
+            triggered_note: Some(72),
+        }, data);
+
+        let data = get_chord_data(&chord, 35, 3, 12, 36, NoteRange::default());
+        assert_eq!(PatternChordData {
+            octave: -1,
+            chord_idx: 2,
+            triggered_note: Some(64),
+        }, data);
+
+        let data = get_chord_data(&chord, 45, 3, 12, 36, NoteRange::default());
+        assert_eq!(PatternChordData {
+            octave: 3,
+            chord_idx: 0,
+            triggered_note: Some(108),
+        }, data);
+    }
+
+    #[test]
+    fn test_note_range() {
+        let midi = |out_of_range| NoteRange { out_of_range, ..Default::default() };
+        assert_eq!(midi(OutOfRange::Drop).apply(60), Some(60));
+        assert_eq!(midi(OutOfRange::Drop).apply(-1), None);
+        assert_eq!(midi(OutOfRange::Drop).apply(128), None);
+
+        assert_eq!(midi(OutOfRange::Fold).apply(60), Some(60));
+        assert_eq!(midi(OutOfRange::Fold).apply(-1), Some(11));
+        assert_eq!(midi(OutOfRange::Fold).apply(-24), Some(0));
+        assert_eq!(midi(OutOfRange::Fold).apply(128), Some(116));
+        assert_eq!(midi(OutOfRange::Fold).apply(139), Some(127));
+
+        assert_eq!(midi(OutOfRange::Clamp).apply(60), Some(60));
+        assert_eq!(midi(OutOfRange::Clamp).apply(-1), Some(0));
+        assert_eq!(midi(OutOfRange::Clamp).apply(200), Some(127));
+
+        // limits
+        let bass = |out_of_range| NoteRange { low: 36, high: 59, out_of_range };
+        assert_eq!(bass(OutOfRange::Drop).apply(64), None);
+        assert_eq!(bass(OutOfRange::Fold).apply(64), Some(52));
+        assert_eq!(bass(OutOfRange::Fold).apply(31), Some(43));
+        assert_eq!(bass(OutOfRange::Clamp).apply(64), Some(59));
+        assert_eq!(bass(OutOfRange::Clamp).apply(31), Some(36));
+        assert_eq!(NoteRange { low: 59, high: 36, out_of_range: OutOfRange::Fold }.apply(64), Some(52));
+
+        // a range smaller than an octave
+        let narrow = NoteRange { low: 60, high: 64, out_of_range: OutOfRange::Fold };
+        assert_eq!(narrow.apply(50), Some(62));
+        assert_eq!(narrow.apply(67), None);
+    }
+
+    #[test]
+    fn test_get_chord_data_all_notes() {
+        // every chord note with every pattern key, for the largest octave range and the extremes of the root. With a
+        // wrap threshold of 1 every key plays an octave of the single chord note.
+        for chord_note in 0..=127u8 {
+            for pattern_note in 0..=127u8 {
+                for (octave_range, root_note) in [(127u8, 0u8), (127, 127), (12, 60), (1, 127)] {
+                    let pitch = chord_note as i32 + octave_range as i32 * (pattern_note as i32 - root_note as i32);
+                    let note = |out_of_range| {
+                        let note_range = NoteRange { out_of_range, ..Default::default() };
+                        get_chord_data(&[chord_note], pattern_note, 1, octave_range, root_note, note_range).triggered_note
+                    };
+
+                    if (0..=127).contains(&pitch) {
+                        for out_of_range in [OutOfRange::Drop, OutOfRange::Fold, OutOfRange::Clamp] {
+                            assert_eq!(note(out_of_range), Some(pitch as u8));
+                        }
+                    } else {
+                        assert_eq!(note(OutOfRange::Drop), None);
+                        assert_eq!(note(OutOfRange::Clamp), Some(if pitch < 0 { 0 } else { 127 }));
+
+                        // the nearest octave of the pitch in the range
+                        let folded = note(OutOfRange::Fold).unwrap() as i32;
+                        assert_eq!((folded - pitch).rem_euclid(12), 0);
+                        assert!(if pitch < 0 { folded < 12 } else { folded > 115 });
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_keyboard_mode_all_notes() {
+        // with every root, a black key plays the chord index and octave of the white key to its right or left, and a
+        // white key plays the same in every mode that skips black keys
+        let chord_data = |pattern_note: Option<u8>, root_note: u8| pattern_note.map(|pattern_note| {
+            let data = get_chord_data(&[60, 64, 67], pattern_note, 3, 12, root_note, NoteRange::default());
+            (data.chord_idx, data.octave)
+        });
+        for root_note in 0..=127u8 {
+            let play = |raw_note: u8, keyboard_mode: KeyboardMode| {
+                chord_data(raw_note_apply_keyboard_mode(raw_note, &keyboard_mode, root_note), root_note)
+            };
+            for raw_note in 0..=127u8 {
+                assert_eq!(play(raw_note, KeyboardMode::AllKeys), chord_data(Some(raw_note), root_note));
+
+                if is_black_key(raw_note) {
+                    assert_eq!(play(raw_note, KeyboardMode::IgnoreBlackKeys), None);
+                    assert_eq!(play(raw_note, KeyboardMode::ShiftBlackKeysRight),
+                               play(raw_note + 1, KeyboardMode::IgnoreBlackKeys));
+                    assert_eq!(play(raw_note, KeyboardMode::ShiftBlackKeysLeft),
+                               play(raw_note - 1, KeyboardMode::IgnoreBlackKeys));
+                } else {
+                    let white_key = play(raw_note, KeyboardMode::IgnoreBlackKeys);
+                    assert_eq!(play(raw_note, KeyboardMode::ShiftBlackKeysRight), white_key);
+                    assert_eq!(play(raw_note, KeyboardMode::ShiftBlackKeysLeft), white_key);
+                }
+            }
+
+            // the white keys from the root on walk through the chord indices
+            let white_keys = (root_note..=127).filter(|note| !is_black_key(*note));
+            for (steps, raw_note) in white_keys.enumerate() {
+                let expected = ((steps % 3) as u8, (steps / 3) as i32);
+                assert_eq!(play(raw_note, KeyboardMode::IgnoreBlackKeys), Some(expected));
+            }
+        }
+    }
 }
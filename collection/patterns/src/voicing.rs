@@ -0,0 +1,233 @@
+use crate::chord::Chord;
+use crate::utils::VoicingDrop;
+
+/// Transforms the held chord before the pattern keys are mapped to it, so different patterns can voice the same chord
+/// differently. The notes keep their position in the chord, so the chord order still applies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Voicing {
+    /// the amount of times the lowest note is moved up an octave
+    pub inversion: u8,
+    pub drop: VoicingDrop,
+    /// moves every other note up an octave, starting with the second lowest
+    pub spread: bool,
+    /// adds the lowest note an octave lower
+    pub bass_octave: bool,
+    /// notes are moved by octaves into the window between the lowest and the highest note. Notes that do not fit are
+    /// left out.
+    pub window_low: u8,
+    pub window_high: u8,
+    /// moves the notes of a new chord by octaves to the notes of the previous chord with the same chord index
+    pub voice_leading: bool,
+}
+
+impl Default for Voicing {
+    fn default() -> Self {
+        Self {
+            inversion: 0,
+            drop: VoicingDrop::None,
+            spread: false,
+            bass_octave: false,
+            window_low: 0,
+            window_high: 127,
+            voice_leading: false,
+        }
+    }
+}
+
+impl Voicing {
+    /// Writes the voiced chord to `voiced`.
+    pub fn apply(&self, chord: &Chord, voiced: &mut Chord) {
+        voiced.clone_from(chord);
+        let mut sorted = Chord::default();
+
+        for _ in 0..self.inversion {
+            match voiced.lowest() {
+                Some(lowest) if lowest + 12 <= 127 => voiced.replace(lowest, lowest + 12),
+                _ => break,
+            }
+        }
+
+        let dropped_voice = match self.drop {
+            VoicingDrop::None => None,
+            VoicingDrop::Drop2 => Some(2),
+            VoicingDrop::Drop3 => Some(3),
+        };
+        if let Some(dropped_voice) = dropped_voice {
+            sorted.clone_from(voiced);
+            sorted.sort();
+            // the voices are counted from the top
+            if let Some(note) = sorted.len().checked_sub(dropped_voice).map(|idx| sorted.as_slice()[idx]) {
+                if note >= 12 {
+                    voiced.replace(note, note - 12);
+                }
+            }
+        }
+
+        if self.spread {
+            sorted.clone_from(voiced);
+            sorted.sort();
+            for note in sorted.iter().skip(1).step_by(2).copied() {
+                if note + 12 <= 127 {
+                    voiced.replace(note, note + 12);
+                }
+            }
+        }
+
+        if self.bass_octave {
+            if let Some(lowest) = voiced.lowest().filter(|lowest| *lowest >= 12) {
+                voiced.insert_with_velocity(lowest - 12, voiced.velocity(lowest));
+            }
+        }
+
+        if self.window_low > 0 || self.window_high < 127 {
+            sorted.clone_from(voiced);
+            for note in sorted.iter().copied() {
+                let mut clamped = note;
+                while clamped < self.window_low && clamped + 12 <= 127 {
+                    clamped += 12;
+                }
+                while clamped > self.window_high && clamped >= 12 {
+                    clamped -= 12;
+                }
+
+                if (self.window_low..=self.window_high).contains(&clamped) {
+                    voiced.replace(note, clamped);
+                } else {
+                    voiced.remove(note);
+                }
+            }
+        }
+    }
+
+    /// Moves every note of the ordered `chord` by octaves, so it is as close as possible to the note with the same
+    /// chord index in the `previous` chord. Additional notes are led from the last note of the previous chord. The
+    /// notes stay in the window if they can.
+    pub fn lead(&self, previous: &Chord, chord: &mut Chord) {
+        let last_previous = match previous.as_slice().last() {
+            Some(note) => *note,
+            None => return,
+        };
+
+        let mut led = Chord::default();
+        for (idx, note) in chord.iter().copied().enumerate() {
+            let target = previous.as_slice().get(idx).copied().unwrap_or(last_previous);
+            let in_window = |candidate: &u8| (self.window_low..=self.window_high).contains(candidate);
+            let candidates = || (note % 12..128).step_by(12).filter(|candidate| !led.contains(*candidate));
+
+            let closest = candidates().filter(in_window).min_by_key(|candidate| candidate.abs_diff(target))
+                .or_else(|| candidates().min_by_key(|candidate| candidate.abs_diff(target)));
+            if let Some(closest) = closest {
+                led.insert_with_velocity(closest, chord.velocity(note));
+            }
+        }
+        chord.clone_from(&led);
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::chord::Chord;
+    use crate::utils::VoicingDrop;
+    use crate::voicing::Voicing;
+
+    fn voiced(chord: &[u8], voicing: Voicing) -> Vec<u8> {
+        let mut chord_notes = Chord::default();
+        for note in chord {
+            chord_notes.insert(*note);
+        }
+        let mut voiced = Chord::default();
+        voicing.apply(&chord_notes, &mut voiced);
+        voiced.as_slice().to_vec()
+    }
+
+    #[test]
+    fn test_no_voicing() {
+        assert_eq!(voiced(&[64, 60, 67], Voicing::default()), [64, 60, 67]);
+        assert!(voiced(&[], Voicing { inversion: 2, spread: true, bass_octave: true, ..Default::default() }).is_empty());
+    }
+
+    #[test]
+    fn test_inversion() {
+        assert_eq!(voiced(&[60, 64, 67], Voicing { inversion: 1, ..Default::default() }), [72, 64, 67]);
+        assert_eq!(voiced(&[60, 64, 67], Voicing { inversion: 2, ..Default::default() }), [72, 76, 67]);
+        assert_eq!(voiced(&[60, 64, 67], Voicing { inversion: 3, ..Default::default() }), [72, 76, 79]);
+        // the top of the note range stops the inversion
+        assert_eq!(voiced(&[110, 120], Voicing { inversion: 2, ..Default::default() }), [122, 120]);
+    }
+
+    #[test]
+    fn test_drop() {
+        let c_maj_7 = [60, 64, 67, 71];
+        assert_eq!(voiced(&c_maj_7, Voicing { drop: VoicingDrop::Drop2, ..Default::default() }), [60, 64, 55, 71]);
+        assert_eq!(voiced(&c_maj_7, Voicing { drop: VoicingDrop::Drop3, ..Default::default() }), [60, 52, 67, 71]);
+        // not enough voices
+        assert_eq!(voiced(&[60, 64], Voicing { drop: VoicingDrop::Drop3, ..Default::default() }), [60, 64]);
+    }
+
+    #[test]
+    fn test_spread() {
+        assert_eq!(voiced(&[60, 64, 67], Voicing { spread: true, ..Default::default() }), [60, 76, 67]);
+        assert_eq!(voiced(&[60, 64, 67, 71], Voicing { spread: true, ..Default::default() }), [60, 76, 67, 83]);
+    }
+
+    #[test]
+    fn test_bass_octave() {
+        assert_eq!(voiced(&[64, 60, 67], Voicing { bass_octave: true, ..Default::default() }), [64, 60, 67, 48]);
+        assert_eq!(voiced(&[5, 9], Voicing { bass_octave: true, ..Default::default() }), [5, 9]);
+    }
+
+    #[test]
+    fn test_window() {
+        let bass = Voicing { window_low: 36, window_high: 47, ..Default::default() };
+        assert_eq!(voiced(&[60, 64, 67], bass), [36, 40, 43]);
+        // notes that end up on the same key are only played once
+        assert_eq!(voiced(&[60, 64, 72], bass), [36, 40]);
+
+        // a window smaller than an octave leaves notes out
+        let narrow = Voicing { window_low: 60, window_high: 65, ..Default::default() };
+        assert_eq!(voiced(&[48, 52, 55], narrow), [60, 64]);
+    }
+
+    #[test]
+    fn test_combined() {
+        // the doubled bass of the first inversion falls below the window and is folded back onto the chord
+        let voicing = Voicing { inversion: 1, bass_octave: true, window_low: 48, window_high: 84, ..Default::default() };
+        assert_eq!(voiced(&[48, 52, 55], voicing), [60, 52, 55]);
+    }
+
+    #[test]
+    fn test_lead() {
+        let voicing = Voicing { voice_leading: true, ..Default::default() };
+        let c_major = Chord::from([60, 64, 67]);
+
+        // every chord index moves by the smallest interval
+        let mut g_major = Chord::from([67, 71, 74]);
+        voicing.lead(&c_major, &mut g_major);
+        assert_eq!(g_major.as_slice(), [55, 59, 62]);
+
+        // the fourth note is led from the last note of the previous chord
+        let mut g_7 = Chord::from([43, 47, 50, 53]);
+        voicing.lead(&c_major, &mut g_7);
+        assert_eq!(g_7.as_slice(), [55, 59, 62, 65]);
+
+        // two notes never end up on the same key
+        let mut octaves = Chord::from([48, 60]);
+        voicing.lead(&Chord::from([60]), &mut octaves);
+        assert_eq!(octaves.as_slice(), [60, 48]);
+
+        // the notes stay in the window
+        let window = Voicing { window_low: 60, window_high: 84, ..voicing };
+        let mut g_major = Chord::from([67, 71, 74]);
+        window.lead(&c_major, &mut g_major);
+        assert_eq!(g_major.as_slice(), [67, 71, 62]);
+
+        // without a previous chord nothing changes
+        let mut g_major = Chord::from([67, 71, 74]);
+        voicing.lead(&Chord::default(), &mut g_major);
+        assert_eq!(g_major.as_slice(), [67, 71, 74]);
+    }
+}
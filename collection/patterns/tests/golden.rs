@@ -0,0 +1,200 @@
+//! Golden-file tests for the output of Modular::Patterns.
+//!
+//! Every scenario in `tests/scenarios` is a `<name>.scenario` file that is played through the plugin in the test host,
+//! and a `<name>.expected` file with the events the plugin has to send. One line per event or parameter change:
+//!
+//! ```text
+//! # comment
+//! <sample> on <channel> <note> [<velocity>]
+//! <sample> off <channel> <note>
+//! <sample> set <parameter id> <plain value>
+//! end <sample>
+//! ```
+//!
+//! Channels are 1-16, velocities 0-127. Run the tests with `UPDATE_GOLDEN=1` to write the current output to the
+//! expected files after an intended change.
+
+use modular_host::{set_parameter, TestHost};
+use modular_patterns::Patterns;
+use nih_plug::prelude::*;
+use std::path::PathBuf;
+
+const BLOCK_SIZE: u32 = 64;
+
+enum Step {
+    Event(NoteEvent<()>),
+    Set(u32, String, f32),
+}
+
+fn scenario_path(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("scenarios").join(format!("{}.{}", name, extension))
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<&str>, line: &str) -> T {
+    value.and_then(|value| value.parse().ok()).unwrap_or_else(|| panic!("Invalid scenario line: {}", line))
+}
+
+/// Parses a scenario into its steps and the number of samples to run.
+fn parse_scenario(scenario: &str) -> (Vec<Step>, u32) {
+    let mut steps = vec![];
+    let mut end = None;
+
+    for line in scenario.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let mut words = line.split_whitespace();
+        let first = words.next();
+        if first == Some("end") {
+            end = Some(parse_number(words.next(), line));
+            continue;
+        }
+
+        let timing: u32 = parse_number(first, line);
+        let step = match words.next() {
+            Some("on") => Step::Event(NoteEvent::NoteOn {
+                timing,
+                voice_id: None,
+                channel: parse_number::<u8>(words.next(), line) - 1,
+                note: parse_number(words.next(), line),
+                velocity: words.next().map_or(127.0, |velocity| parse_number::<f32>(Some(velocity), line)) / 127.0,
+            }),
+            Some("off") => Step::Event(NoteEvent::NoteOff {
+                timing,
+                voice_id: None,
+                channel: parse_number::<u8>(words.next(), line) - 1,
+                note: parse_number(words.next(), line),
+                velocity: 0.0,
+            }),
+            Some("set") => Step::Set(timing, words.next().unwrap_or_default().to_string(), parse_number(words.next(), line)),
+            _ => panic!("Invalid scenario line: {}", line),
+        };
+        steps.push(step);
+    }
+
+    let last = steps.iter().map(|step| match step {
+        Step::Event(event) => event.timing(),
+        Step::Set(timing, _, _) => *timing,
+    }).max().unwrap_or(0);
+    (steps, end.unwrap_or(last + 1))
+}
+
+fn format_event(event: &NoteEvent<()>) -> String {
+    match event {
+        NoteEvent::NoteOn { timing, channel, note, velocity, .. } =>
+            format!("{} on {} {} {}", timing, channel + 1, note, (velocity * 127.0).round()),
+        NoteEvent::NoteOff { timing, channel, note, velocity, .. } =>
+            format!("{} off {} {} {}", timing, channel + 1, note, (velocity * 127.0).round()),
+        NoteEvent::MidiCC { timing, channel, cc, value } =>
+            format!("{} cc {} {} {}", timing, channel + 1, cc, (value * 127.0).round()),
+        _ => format!("{} other {:?}", event.timing(), event),
+    }
+}
+
+/// Plays a scenario through the plugin and returns the sent events, one formatted event per line.
+fn run_scenario(scenario: &str) -> String {
+    let (steps, end) = parse_scenario(scenario);
+    let mut host = TestHost::new(BLOCK_SIZE);
+    let mut plugin = Patterns::default();
+    assert!(host.initialize(&mut plugin));
+
+    for step in steps {
+        match step {
+            Step::Event(event) => host.queue_event(event),
+            Step::Set(timing, id, value) => {
+                if timing > host.position() {
+                    host.run(&mut plugin, timing - host.position());
+                }
+                assert!(set_parameter(&plugin, &id, value), "Unknown parameter: {}", id);
+            }
+        }
+    }
+    if end > host.position() {
+        host.run(&mut plugin, end - host.position());
+    }
+
+    host.output_events().iter().map(|event| format_event(event) + "\n").collect()
+}
+
+/// A line diff of the expected and the actual output, based on their longest common subsequence.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let mut lengths = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff += &format!("  {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            diff += &format!("- {}\n", expected[i]);
+            i += 1;
+        } else {
+            diff += &format!("+ {}\n", actual[j]);
+            j += 1;
+        }
+    }
+    diff
+}
+
+fn check(name: &str) {
+    let scenario = std::fs::read_to_string(scenario_path(name, "scenario")).unwrap();
+    let actual = run_scenario(&scenario);
+
+    let expected_path = scenario_path(name, "expected");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&expected_path, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+    if expected != actual {
+        panic!("The output of {} differs (- expected, + actual):\n{}\nRun with UPDATE_GOLDEN=1 to accept the change.",
+               name, diff(&expected, &actual));
+    }
+}
+
+#[test]
+fn test_chord_change_mid_note() {
+    check("chord_change_mid_note");
+}
+
+#[test]
+fn test_overlapping_chords() {
+    check("overlapping_chords");
+}
+
+#[test]
+fn test_overlapping_chords_bridged() {
+    check("overlapping_chords_bridged");
+}
+
+#[test]
+fn test_ignore_black_keys() {
+    check("ignore_black_keys");
+}
+
+#[test]
+fn test_negative_octaves() {
+    check("negative_octaves");
+}
+
+#[test]
+fn test_auto_threshold() {
+    check("auto_threshold");
+}
+
+#[test]
+fn test_diff() {
+    assert_eq!(diff("1\n2\n3\n", "1\n3\n4\n"), "  1\n- 2\n  3\n+ 4\n");
+}
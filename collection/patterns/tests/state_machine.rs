@@ -0,0 +1,40 @@
+//! Property tests of the pattern state machine: random chord and pattern events with parameter changes in between must
+//! never leave a note hanging. The fuzz target in `fuzz/` runs the same check on fuzzer input.
+
+use modular_host::{check_session, SessionStep};
+use modular_patterns::Patterns;
+use proptest::prelude::*;
+
+fn session_step() -> impl Strategy<Value = SessionStep> {
+    // a few channels, including the default chord channel, and a small range of notes so that keys collide often
+    let channel = prop::sample::select(vec![0u8, 1, 15]);
+    let note = 48u8..72;
+
+    prop_oneof![
+        4 => (channel.clone(), note.clone(), 0.0f32..=1.0)
+            .prop_map(|(channel, note, velocity)| SessionStep::NoteOn { channel, note, velocity }),
+        4 => (channel, note).prop_map(|(channel, note)| SessionStep::NoteOff { channel, note }),
+        1 => (any::<usize>(), 0.0f32..=1.0)
+            .prop_map(|(param_idx, normalized)| SessionStep::SetParameter { param_idx, normalized }),
+        3 => (0u32..300).prop_map(SessionStep::Wait),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(500))]
+
+    #[test]
+    fn test_no_hanging_notes(steps in prop::collection::vec(session_step(), 0..200),
+                             block_size in prop::sample::select(vec![16u32, 64, 512])) {
+        let mut plugin = Patterns::default();
+        prop_assert_eq!(check_session(&mut plugin, &steps, block_size), Ok(()));
+    }
+}
+
+#[test]
+fn test_fuzz_input() {
+    // the decoding the fuzz target uses, e.g. to replay an input it found
+    let data: Vec<u8> = (0..=255u8).cycle().step_by(7).take(1024).collect();
+    let mut plugin = Patterns::default();
+    assert_eq!(check_session(&mut plugin, &SessionStep::from_bytes(&data), 64), Ok(()));
+}
@@ -0,0 +1,75 @@
+use nih_plug::prelude::*;
+
+/// The parts of the transport the plugins use. Unlike nih_plug's `Transport` it can be constructed outside of
+/// nih_plug, so the plugins can be driven by the [`TestHost`](crate::TestHost) as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransportInfo {
+    pub playing: bool,
+    pub tempo: Option<f64>,
+    pub pos_samples: Option<i64>,
+    pub sample_rate: f32,
+}
+
+impl Default for TransportInfo {
+    fn default() -> Self {
+        Self {
+            playing: false,
+            tempo: Some(120.0),
+            pos_samples: Some(0),
+            sample_rate: 44_100.0,
+        }
+    }
+}
+
+impl From<&Transport> for TransportInfo {
+    fn from(transport: &Transport) -> Self {
+        Self {
+            playing: transport.playing,
+            tempo: transport.tempo,
+            pos_samples: transport.pos_samples(),
+            sample_rate: transport.sample_rate,
+        }
+    }
+}
+
+/// What a plugin needs from the host during one process call: the transport, the incoming events and a way to send
+/// events.
+pub trait HostContext<P: Plugin> {
+    fn transport(&self) -> TransportInfo;
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<P>>;
+
+    fn send_event(&mut self, event: PluginNoteEvent<P>);
+}
+
+/// A plugin whose processing does not need the audio buffer. `Plugin::process` forwards to `process_events` with a
+/// [`NihContext`], the [`TestHost`](crate::TestHost) calls it directly. The test host cannot implement `ProcessContext`
+/// itself, because nih_plug's `Transport` can only be constructed by nih_plug.
+pub trait HeadlessPlugin: Plugin {
+    fn process_events(&mut self, num_samples: u32, context: &mut impl HostContext<Self>) -> ProcessStatus;
+}
+
+/// Wraps the `ProcessContext` of nih_plug.
+pub struct NihContext<'a, C> {
+    context: &'a mut C,
+}
+
+impl<'a, C> NihContext<'a, C> {
+    pub fn new(context: &'a mut C) -> Self {
+        Self { context }
+    }
+}
+
+impl<P: Plugin, C: ProcessContext<P>> HostContext<P> for NihContext<'_, C> {
+    fn transport(&self) -> TransportInfo {
+        TransportInfo::from(self.context.transport())
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<P>> {
+        self.context.next_event()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<P>) {
+        self.context.send_event(event)
+    }
+}
@@ -0,0 +1,13 @@
+mod context;
+mod midi;
+#[cfg(feature = "test-host")]
+mod session;
+#[cfg(feature = "test-host")]
+mod test_host;
+
+pub use crate::context::{HeadlessPlugin, HostContext, NihContext, TransportInfo};
+pub use crate::midi::{is_panic, ALL_NOTES_OFF, ALL_SOUND_OFF};
+#[cfg(feature = "test-host")]
+pub use crate::session::{check_note_pairs, check_session, SessionStep};
+#[cfg(feature = "test-host")]
+pub use crate::test_host::{set_event_timing, set_parameter, TestHost, TestInitContext};
@@ -0,0 +1,30 @@
+use nih_plug::prelude::*;
+
+/// MIDI CC 120, All Sound Off
+pub const ALL_SOUND_OFF: u8 = 120;
+/// MIDI CC 123, All Notes Off
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// Whether the event is a MIDI panic message. The plugins end every note they play when they receive one.
+pub fn is_panic<S>(event: &NoteEvent<S>) -> bool {
+    matches!(event, NoteEvent::MidiCC { cc: ALL_SOUND_OFF | ALL_NOTES_OFF, .. })
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::prelude::*;
+    use crate::midi::is_panic;
+
+    #[test]
+    fn test_is_panic() {
+        let cc = |cc| NoteEvent::<()>::MidiCC { timing: 0, channel: 3, cc, value: 0.0 };
+        assert!(is_panic(&cc(120)));
+        assert!(is_panic(&cc(123)));
+        assert!(!is_panic(&cc(64)));
+        assert!(!is_panic(&NoteEvent::<()>::NoteOff { timing: 0, voice_id: None, channel: 0, note: 123, velocity: 0.0 }));
+    }
+}
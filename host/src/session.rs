@@ -0,0 +1,138 @@
+use nih_plug::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::context::HeadlessPlugin;
+use crate::test_host::TestHost;
+
+/// One step of a session that is played into a plugin, e.g. generated by a property test or decoded from fuzzer input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionStep {
+    NoteOn { channel: u8, note: u8, velocity: f32 },
+    NoteOff { channel: u8, note: u8 },
+    /// sets the parameter at this position of the plugin's parameter map, modulo its length, to a normalized value
+    SetParameter { param_idx: usize, normalized: f32 },
+    /// the following steps happen this many samples later
+    Wait(u32),
+}
+
+impl SessionStep {
+    /// Decodes arbitrary bytes into steps, four bytes per step. Every input is a valid session.
+    pub fn from_bytes(data: &[u8]) -> Vec<SessionStep> {
+        data.chunks_exact(4)
+            .map(|bytes| match bytes[0] % 4 {
+                0 => SessionStep::NoteOn { channel: bytes[1] % 16, note: bytes[2] % 128, velocity: bytes[3] as f32 / 255.0 },
+                1 => SessionStep::NoteOff { channel: bytes[1] % 16, note: bytes[2] % 128 },
+                2 => SessionStep::SetParameter {
+                    param_idx: bytes[1] as usize,
+                    normalized: u16::from_le_bytes([bytes[2], bytes[3]]) as f32 / u16::MAX as f32,
+                },
+                _ => SessionStep::Wait(u16::from_le_bytes([bytes[1], bytes[2]]) as u32),
+            })
+            .collect()
+    }
+}
+
+/// Plays the steps into the plugin in the [`TestHost`], releases every key that is still held and checks the notes the
+/// plugin sends with [`check_note_pairs`].
+pub fn check_session<P: HeadlessPlugin>(plugin: &mut P, steps: &[SessionStep], block_size: u32) -> Result<(), String> {
+    let mut host = TestHost::new(block_size);
+    if !host.initialize(plugin) {
+        return Err("The plugin did not initialize".to_string());
+    }
+
+    let params = plugin.params();
+    let param_map = params.param_map();
+    let mut held_keys = BTreeSet::new();
+    let mut time = 0;
+
+    for step in steps {
+        match *step {
+            SessionStep::NoteOn { channel, note, velocity } => {
+                host.queue_event(NoteEvent::NoteOn { timing: time, voice_id: None, channel, note, velocity });
+                held_keys.insert((channel, note));
+            }
+            SessionStep::NoteOff { channel, note } => {
+                host.queue_event(NoteEvent::NoteOff { timing: time, voice_id: None, channel, note, velocity: 0.0 });
+                held_keys.remove(&(channel, note));
+            }
+            SessionStep::SetParameter { param_idx, normalized } => {
+                if param_map.is_empty() {
+                    continue;
+                }
+                if time > host.position() {
+                    host.run(plugin, time - host.position());
+                }
+                let (_, param_ptr, _) = &param_map[param_idx % param_map.len()];
+                // the parameters outlive this call and are not accessed from another thread
+                unsafe { param_ptr.set_normalized_value(normalized.clamp(0.0, 1.0)) };
+            }
+            SessionStep::Wait(samples) => time += samples,
+        }
+    }
+
+    for (channel, note) in held_keys {
+        host.queue_event(NoteEvent::NoteOff { timing: time, voice_id: None, channel, note, velocity: 0.0 });
+    }
+    host.run(plugin, time + block_size - host.position());
+
+    check_note_pairs(host.output_events())
+}
+
+/// Checks that every NoteOff ends a sounding NoteOn of the same channel and note, and that no note is left sounding.
+/// A note that is started several times has to be ended as often.
+pub fn check_note_pairs<S>(events: &[NoteEvent<S>]) -> Result<(), String> {
+    let mut sounding: BTreeMap<(u8, u8), u32> = BTreeMap::new();
+
+    for event in events {
+        match *event {
+            NoteEvent::NoteOn { channel, note, .. } => *sounding.entry((channel, note)).or_default() += 1,
+            NoteEvent::NoteOff { timing, channel, note, .. } => match sounding.get_mut(&(channel, note)) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => return Err(format!("NoteOff of note {} on channel {} at {} does not end a NoteOn",
+                                        note, channel + 1, timing)),
+            },
+            _ => {}
+        }
+    }
+
+    match sounding.into_iter().find(|(_, count)| *count > 0) {
+        Some(((channel, note), _)) => Err(format!("Note {} on channel {} is still sounding", note, channel + 1)),
+        None => Ok(()),
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::prelude::*;
+    use crate::session::{check_note_pairs, SessionStep};
+
+    fn note_on(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn { timing, voice_id: None, channel: 0, note, velocity: 1.0 }
+    }
+
+    fn note_off(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOff { timing, voice_id: None, channel: 0, note, velocity: 1.0 }
+    }
+
+    #[test]
+    fn test_check_note_pairs() {
+        assert_eq!(check_note_pairs(&[note_on(0, 60), note_on(5, 60), note_off(10, 60), note_off(20, 60)]),
+                   Ok(()));
+        assert!(check_note_pairs(&[note_on(0, 60)]).is_err());
+        assert!(check_note_pairs(&[note_on(0, 60), note_off(10, 60), note_off(20, 60)]).is_err());
+        assert!(check_note_pairs(&[note_off(10, 61)]).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        assert_eq!(SessionStep::from_bytes(&[0, 17, 60, 255, 1, 0, 60, 0, 2, 3, 255, 255, 3, 16, 0, 0, 0]), [
+            SessionStep::NoteOn { channel: 1, note: 60, velocity: 1.0 },
+            SessionStep::NoteOff { channel: 0, note: 60 },
+            SessionStep::SetParameter { param_idx: 3, normalized: 1.0 },
+            SessionStep::Wait(16),
+        ]);
+    }
+}
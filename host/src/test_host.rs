@@ -0,0 +1,290 @@
+use nih_plug::prelude::*;
+use std::collections::VecDeque;
+use crate::context::{HeadlessPlugin, HostContext, TransportInfo};
+
+/// A scriptable host that runs a plugin without a DAW. Events are queued and captured with absolute sample positions
+/// as their timing. They are split into blocks of `block_size` samples like a real host would do.
+pub struct TestHost<P: Plugin> {
+    pub transport: TransportInfo,
+    pub block_size: u32,
+    /// the sample position of the next block
+    position: u32,
+    input_events: Vec<PluginNoteEvent<P>>,
+    block_events: VecDeque<PluginNoteEvent<P>>,
+    output_events: Vec<PluginNoteEvent<P>>,
+}
+
+impl<P: Plugin> Default for TestHost<P> {
+    fn default() -> Self {
+        Self {
+            transport: TransportInfo::default(),
+            block_size: 512,
+            position: 0,
+            input_events: vec![],
+            block_events: VecDeque::new(),
+            output_events: vec![],
+        }
+    }
+}
+
+impl<P: Plugin> TestHost<P> {
+    pub fn new(block_size: u32) -> Self {
+        Self {
+            block_size,
+            ..Default::default()
+        }
+    }
+
+    /// The sample position of the next block.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Queues an event. Its timing is the absolute sample position it arrives at. Events that are queued for a
+    /// position that has already been processed arrive at the start of the next block.
+    pub fn queue_event(&mut self, event: PluginNoteEvent<P>) {
+        // events of the same timing keep the order they were queued in
+        let idx = self.input_events.iter().take_while(|e| e.timing() <= event.timing()).count();
+        self.input_events.insert(idx, event);
+    }
+
+    /// The events the plugin has sent so far, with the absolute sample position as their timing.
+    pub fn output_events(&self) -> &[PluginNoteEvent<P>] {
+        &self.output_events
+    }
+
+    pub fn take_output_events(&mut self) -> Vec<PluginNoteEvent<P>> {
+        std::mem::take(&mut self.output_events)
+    }
+
+    /// Reserves room for the queued events and for `num_output_events` more sent events, so running the plugin does
+    /// not allocate in the host. This lets tests check that the plugin itself does not allocate.
+    pub fn reserve(&mut self, num_output_events: usize) {
+        self.block_events.reserve(self.input_events.len());
+        self.output_events.reserve(num_output_events);
+    }
+
+    /// Initializes the plugin with the block size and sample rate of the host and resets it, like a host does before
+    /// processing starts.
+    pub fn initialize(&mut self, plugin: &mut P) -> bool {
+        let no_busses = || AuxiliaryIOConfig { num_busses: 0, num_channels: 0 };
+        let bus_config = BusConfig {
+            num_input_channels: P::DEFAULT_INPUT_CHANNELS,
+            num_output_channels: P::DEFAULT_OUTPUT_CHANNELS,
+            aux_input_busses: P::DEFAULT_AUX_INPUTS.unwrap_or_else(no_busses),
+            aux_output_busses: P::DEFAULT_AUX_OUTPUTS.unwrap_or_else(no_busses),
+        };
+        let buffer_config = BufferConfig {
+            sample_rate: self.transport.sample_rate,
+            min_buffer_size: None,
+            max_buffer_size: self.block_size,
+            process_mode: ProcessMode::Realtime,
+        };
+
+        let initialized = plugin.initialize(&bus_config, &buffer_config, &mut TestInitContext);
+        if initialized {
+            plugin.reset();
+        }
+        initialized
+    }
+
+    /// Processes `num_samples` samples in blocks of at most `block_size` samples. The position of the transport moves
+    /// on while it is playing.
+    pub fn run(&mut self, plugin: &mut P, num_samples: u32) -> ProcessStatus
+        where P: HeadlessPlugin {
+        let end = self.position + num_samples;
+        let mut status = ProcessStatus::Normal;
+
+        while self.position < end {
+            let block_len = self.block_size.min(end - self.position);
+            let block_end = self.position + block_len;
+
+            let num_events = self.input_events.iter().take_while(|e| e.timing() < block_end).count();
+            for event in self.input_events.drain(..num_events) {
+                let timing = event.timing().saturating_sub(self.position);
+                self.block_events.push_back(set_event_timing::<P>(event, timing));
+            }
+
+            status = plugin.process_events(block_len, self);
+            self.block_events.clear();
+
+            self.position = block_end;
+            if self.transport.playing {
+                self.transport.pos_samples = Some(self.transport.pos_samples.unwrap_or(0) + block_len as i64);
+            }
+        }
+        status
+    }
+}
+
+impl<P: Plugin> HostContext<P> for TestHost<P> {
+    fn transport(&self) -> TransportInfo {
+        self.transport
+    }
+
+    fn next_event(&mut self) -> Option<PluginNoteEvent<P>> {
+        self.block_events.pop_front()
+    }
+
+    fn send_event(&mut self, event: PluginNoteEvent<P>) {
+        let timing = self.position + event.timing();
+        self.output_events.push(set_event_timing::<P>(event, timing));
+    }
+}
+
+/// The `InitContext` of the [`TestHost`]. Background tasks are not run.
+pub struct TestInitContext;
+
+impl<P: Plugin> InitContext<P> for TestInitContext {
+    fn plugin_api(&self) -> PluginApi {
+        PluginApi::Clap
+    }
+
+    fn execute(&self, _task: P::BackgroundTask) {}
+
+    fn set_latency_samples(&self, _samples: u32) {}
+
+    fn set_current_voice_capacity(&self, _capacity: u32) {}
+}
+
+/// Sets a parameter by its id to a plain value, e.g. a note number or the index of an enum. Returns false if the
+/// plugin has no parameter with this id.
+pub fn set_parameter<P: Plugin>(plugin: &P, id: &str, plain_value: f32) -> bool {
+    let params = plugin.params();
+    match params.param_map().into_iter().find(|(param_id, _, _)| param_id == id) {
+        // the parameters outlive this call and are not accessed from another thread in tests
+        Some((_, param_ptr, _)) => unsafe {
+            param_ptr.set_normalized_value(param_ptr.preview_normalized(plain_value));
+            true
+        },
+        None => false,
+    }
+}
+
+/// Returns the event with another timing. Events the plugins do not deal with keep their timing.
+pub fn set_event_timing<P: Plugin>(mut event: PluginNoteEvent<P>, new_timing: u32) -> PluginNoteEvent<P> {
+    match &mut event {
+        NoteEvent::NoteOn { timing, .. }
+        | NoteEvent::NoteOff { timing, .. }
+        | NoteEvent::Choke { timing, .. }
+        | NoteEvent::VoiceTerminated { timing, .. }
+        | NoteEvent::PolyPressure { timing, .. }
+        | NoteEvent::PolyVolume { timing, .. }
+        | NoteEvent::PolyPan { timing, .. }
+        | NoteEvent::PolyTuning { timing, .. }
+        | NoteEvent::PolyVibrato { timing, .. }
+        | NoteEvent::PolyExpression { timing, .. }
+        | NoteEvent::PolyBrightness { timing, .. }
+        | NoteEvent::MidiChannelPressure { timing, .. }
+        | NoteEvent::MidiPitchBend { timing, .. }
+        | NoteEvent::MidiCC { timing, .. }
+        | NoteEvent::MidiProgramChange { timing, .. } => *timing = new_timing,
+        _ => {}
+    }
+    event
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use nih_plug::prelude::*;
+    use std::sync::Arc;
+    use crate::context::{HeadlessPlugin, HostContext};
+    use crate::test_host::TestHost;
+
+    /// Sends every incoming event back one semitone higher and a NoteOn at the start of every block.
+    #[derive(Default)]
+    struct Echo {
+        params: Arc<EchoParams>,
+        block_sizes: Vec<u32>,
+    }
+
+    #[derive(Params, Default)]
+    struct EchoParams {}
+
+    impl Plugin for Echo {
+        const NAME: &'static str = "Echo";
+        const VENDOR: &'static str = "";
+        const URL: &'static str = "";
+        const EMAIL: &'static str = "";
+        const VERSION: &'static str = "0.0.1";
+
+        const DEFAULT_INPUT_CHANNELS: u32 = 0;
+        const DEFAULT_OUTPUT_CHANNELS: u32 = 0;
+
+        const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
+        const MIDI_OUTPUT: MidiConfig = MidiConfig::MidiCCs;
+
+        type SysExMessage = ();
+        type BackgroundTask = ();
+
+        fn params(&self) -> Arc<dyn Params> {
+            self.params.clone()
+        }
+
+        fn process(&mut self, _buffer: &mut Buffer, _aux: &mut AuxiliaryBuffers,
+                   _context: &mut impl ProcessContext<Self>) -> ProcessStatus {
+            ProcessStatus::Normal
+        }
+    }
+
+    impl HeadlessPlugin for Echo {
+        fn process_events(&mut self, num_samples: u32, context: &mut impl HostContext<Self>) -> ProcessStatus {
+            self.block_sizes.push(num_samples);
+            if context.transport().playing {
+                context.send_event(NoteEvent::NoteOn { timing: 0, voice_id: None, channel: 0, note: 0, velocity: 1.0 });
+            }
+            while let Some(event) = context.next_event() {
+                if let NoteEvent::NoteOn { timing, voice_id, channel, note, velocity } = event {
+                    context.send_event(NoteEvent::NoteOn { timing, voice_id, channel, note: note + 1, velocity });
+                }
+            }
+            ProcessStatus::Normal
+        }
+    }
+
+    fn note_on(timing: u32, note: u8) -> NoteEvent<()> {
+        NoteEvent::NoteOn { timing, voice_id: None, channel: 0, note, velocity: 1.0 }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut host = TestHost::new(100);
+        let mut plugin = Echo::default();
+        assert!(host.initialize(&mut plugin));
+
+        host.run(&mut plugin, 250);
+        assert_eq!(plugin.block_sizes, [100, 100, 50]);
+        assert_eq!(host.position(), 250);
+        // the transport only moves while playing
+        assert_eq!(host.transport.pos_samples, Some(0));
+
+        host.transport.playing = true;
+        host.run(&mut plugin, 100);
+        assert_eq!(host.transport.pos_samples, Some(100));
+        assert_eq!(host.take_output_events(), [note_on(250, 0)]);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut host = TestHost::new(100);
+        let mut plugin = Echo::default();
+
+        // queued out of order, the events of the same timing keep their order
+        host.queue_event(note_on(150, 60));
+        host.queue_event(note_on(20, 62));
+        host.queue_event(note_on(150, 64));
+        host.queue_event(note_on(99, 66));
+
+        host.run(&mut plugin, 200);
+        assert_eq!(host.output_events(), [note_on(20, 63), note_on(99, 67), note_on(150, 61), note_on(150, 65)]);
+
+        // late events arrive at the start of the next block
+        host.queue_event(note_on(10, 70));
+        host.run(&mut plugin, 100);
+        assert_eq!(host.output_events()[4..], [note_on(200, 71)]);
+    }
+}
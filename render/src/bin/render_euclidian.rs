@@ -0,0 +1,68 @@
+use modular_euclidian::Euclidian;
+use modular_render::midi::{write_midi_file, TempoMap};
+use modular_render::preset::apply_preset;
+use modular_render::{render, Options, OPTIONS_USAGE};
+use nih_plug::prelude::NoteEvent;
+use std::process::ExitCode;
+
+const TICKS_PER_BEAT: u16 = 480;
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}\n\nUsage: render_euclidian <output.mid> [options]\n{}\n  --bars <bars>           the number of 4/4 bars [default: 4]",
+                      e, OPTIONS_USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Renders the rhythms of Modular::Euclidian for a number of bars.
+fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let output = match options.files.as_slice() {
+        [output] => output,
+        _ => return Err("Expected an output file".to_string()),
+    };
+
+    let tempo_map = TempoMap::new(TICKS_PER_BEAT, options.tempo);
+    let num_samples = tempo_map.tick_to_sample(options.bars as u64 * 4 * TICKS_PER_BEAT as u64, options.sample_rate);
+
+    let mut plugin = Euclidian::default();
+    apply_preset(&plugin, &options.preset)?;
+    // the notes that sound at the end of the last bar end there
+    let output_events: Vec<(u64, NoteEvent<()>)> = render(&mut plugin, &[], &tempo_map, &options, num_samples as u32, 0)?
+        .into_iter()
+        .map(|event| (tempo_map.sample_to_tick(event.timing() as u64, options.sample_rate), event))
+        .collect();
+
+    write_midi_file(output, &tempo_map, &output_events)
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use modular_render::midi::read_midi_file;
+    use crate::run;
+
+    #[test]
+    fn test_render() {
+        // unique for every run of the tests
+        let output = std::env::temp_dir().join(format!("modular_render_euclidian_{}_output.mid", std::process::id()));
+
+        // the default rhythm: two notes in eight 1/8 steps, so two notes per bar
+        let args = [output.to_str().unwrap(), "--bars", "2", "--tempo", "100"];
+        run(args.map(String::from)).unwrap();
+
+        let rendered = read_midi_file(&output, 120.0).unwrap();
+        assert_eq!(rendered.tempo_map.changes(), [(0, 100.0)]);
+        let ticks: Vec<u64> = rendered.events.iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(ticks, [0, 240, 960, 1200, 1920, 2160, 2880, 3120]);
+
+        std::fs::remove_file(&output).unwrap();
+    }
+}
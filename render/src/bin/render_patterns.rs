@@ -0,0 +1,149 @@
+use modular_patterns::Patterns;
+use modular_render::midi::{read_midi_file, write_midi_file};
+use modular_render::preset::apply_preset;
+use modular_render::{render, Options, OPTIONS_USAGE};
+use nih_plug::prelude::NoteEvent;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}\n\nUsage: render_patterns <input.mid> <output.mid> [options]\n{}\n  --tail <seconds>        the longest time the notes sound on after the input [default: 10]",
+                      e, OPTIONS_USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the chord and pattern notes of the input file through Modular::Patterns and writes the notes it plays.
+fn run(args: impl IntoIterator<Item = String>) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let (input, output) = match options.files.as_slice() {
+        [input, output] => (input, output),
+        _ => return Err("Expected an input and an output file".to_string()),
+    };
+
+    let midi_file = read_midi_file(input, options.tempo)?;
+    let tempo_map = &midi_file.tempo_map;
+    let events: Vec<NoteEvent<()>> = midi_file.events.iter()
+        .map(|(tick, event)| {
+            let sample = tempo_map.tick_to_sample(*tick, options.sample_rate);
+            modular_host::set_event_timing::<Patterns>(*event, sample as u32)
+        })
+        .collect();
+    // the events of the last sample are processed as well. Bridged chords and strums can end later, the tail lets
+    // their notes end.
+    let num_samples = events.last().map_or(0, |event| event.timing() + 1);
+    let max_tail = (options.tail * options.sample_rate as f64).round() as u32;
+
+    let mut plugin = Patterns::default();
+    apply_preset(&plugin, &options.preset)?;
+    let output_events: Vec<(u64, NoteEvent<()>)> = render(&mut plugin, &events, tempo_map, &options, num_samples,
+                                                           max_tail)?
+        .into_iter()
+        .map(|event| (tempo_map.sample_to_tick(event.timing() as u64, options.sample_rate), event))
+        .collect();
+
+    write_midi_file(output, tempo_map, &output_events)
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use modular_render::midi::{read_midi_file, write_midi_file, TempoMap};
+    use nih_plug::prelude::NoteEvent;
+    use std::path::PathBuf;
+    use crate::run;
+
+    fn note(tick: u64, channel: u8, note: u8, on: bool) -> (u64, NoteEvent<()>) {
+        let event = if on {
+            NoteEvent::NoteOn { timing: 0, voice_id: None, channel, note, velocity: 1.0 }
+        } else {
+            NoteEvent::NoteOff { timing: 0, voice_id: None, channel, note, velocity: 1.0 }
+        };
+        (tick, event)
+    }
+
+    /// A path in the temporary directory that no other test and no other run of the tests uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("modular_render_patterns_{}_{}", std::process::id(), name))
+    }
+
+    /// Writes the events to the input file, renders it with the arguments and returns the rendered events.
+    fn render_file(name: &str, events: &[(u64, NoteEvent<()>)], args: &[&str]) -> Vec<(u64, NoteEvent<()>)> {
+        let input = temp_path(&format!("{}_input.mid", name));
+        let output = temp_path(&format!("{}_output.mid", name));
+
+        let tempo_map = TempoMap::new(480, 120.0);
+        write_midi_file(&input, &tempo_map, events).unwrap();
+
+        let files = [input.to_str().unwrap(), output.to_str().unwrap()];
+        let result = run(files.iter().chain(args).map(|arg| arg.to_string()));
+        let rendered = result.and_then(|_| read_midi_file(&output, 120.0));
+
+        std::fs::remove_file(&input).unwrap();
+        let _ = std::fs::remove_file(&output);
+        let rendered = rendered.unwrap();
+        assert_eq!(rendered.tempo_map, tempo_map);
+        rendered.events
+    }
+
+    #[test]
+    fn test_render() {
+        // a C major chord on channel 16 and two pattern notes
+        let mut events = vec![];
+        for chord_note in [60, 64, 67] {
+            events.push(note(0, 15, chord_note, true));
+            events.push(note(1920, 15, chord_note, false));
+        }
+        events.extend([note(0, 0, 60, true), note(480, 0, 60, false), note(480, 0, 62, true), note(960, 0, 62, false)]);
+        events.sort_by_key(|(tick, _)| *tick);
+
+        assert_eq!(render_file("render", &events, &["--set", "root_note=60"]), [
+            note(0, 0, 60, true),
+            note(480, 0, 60, false),
+            note(480, 0, 67, true),
+            note(960, 0, 67, false),
+        ]);
+    }
+
+    #[test]
+    fn test_render_tail() {
+        // the file ends while the chord and three pattern keys are held. The strummed notes start after the end of
+        // the input and the notes end after the tail of one second (960 ticks).
+        let events = [
+            note(0, 15, 60, true), note(0, 15, 64, true), note(0, 15, 67, true),
+            note(0, 0, 60, true), note(0, 0, 61, true), note(0, 0, 62, true),
+        ];
+
+        let rendered = render_file("render_tail", &events, &["--set", "strum_time=100", "--tail", "1"]);
+        assert_eq!(rendered, [
+            note(0, 0, 60, true),
+            note(96, 0, 64, true),
+            note(192, 0, 67, true),
+            note(960, 0, 60, false),
+            note(960, 0, 64, false),
+            note(960, 0, 67, false),
+        ]);
+    }
+
+    #[test]
+    fn test_arguments() {
+        assert_eq!(run(["input.mid".to_string()]), Err("Expected an input and an output file".to_string()));
+
+        let input = temp_path("arguments_input.mid");
+        write_midi_file(&input, &TempoMap::new(480, 120.0), &[note(0, 0, 60, true)]).unwrap();
+        let output = temp_path("arguments_output.mid");
+
+        let args = [input.to_str().unwrap(), output.to_str().unwrap(), "--set", "no_such_parameter=1"];
+        let result = run(args.map(String::from));
+        std::fs::remove_file(&input).unwrap();
+
+        assert_eq!(result, Err("Unknown parameter: no_such_parameter".to_string()));
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,120 @@
+pub mod midi;
+pub mod preset;
+
+use nih_plug::prelude::{NoteEvent, Plugin};
+use modular_host::{HeadlessPlugin, TestHost};
+use std::path::PathBuf;
+use crate::midi::TempoMap;
+
+/// The options both renderers share.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub files: Vec<PathBuf>,
+    /// the parameters of the preset file followed by the ones set on the command line
+    pub preset: Vec<(String, f32)>,
+    pub tempo: f64,
+    pub bars: u32,
+    /// the longest time in seconds the plugin plays on after the input until its notes have ended
+    pub tail: f64,
+    pub sample_rate: f32,
+    pub block_size: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            preset: vec![],
+            tempo: 120.0,
+            bars: 4,
+            tail: 10.0,
+            sample_rate: 44_100.0,
+            block_size: 512,
+        }
+    }
+}
+
+pub const OPTIONS_USAGE: &str = "\
+  --preset <file>         sets the parameters of a preset file, one `id = value` per line
+  --set <id>=<value>      sets a parameter, after the preset
+  --tempo <bpm>           the tempo if the input has none [default: 120]
+  --sample-rate <hz>      [default: 44100]
+  --block-size <samples>  the size of the blocks the plugin processes [default: 512]";
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--preset" => options.preset.extend(preset::read_preset(&PathBuf::from(value()?))?),
+                "--set" => options.preset.push(preset::parse_setting(&value()?)?),
+                "--tempo" => options.tempo = parse_number(&value()?)?,
+                "--bars" => options.bars = parse_number(&value()?)?,
+                "--tail" => options.tail = parse_number(&value()?)?,
+                "--sample-rate" => options.sample_rate = parse_number(&value()?)?,
+                "--block-size" => options.block_size = parse_number(&value()?)?,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                _ => options.files.push(PathBuf::from(arg)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Not a valid number: {}", value))
+}
+
+/// Runs the events through the plugin like a playing host would and returns the events the plugin sends. The
+/// timings of the events are absolute sample positions. After `num_samples` the plugin plays on until its notes have
+/// ended, at most for `max_tail` samples. Then the transport stops, which ends the notes that are still sounding.
+pub fn render<P>(plugin: &mut P, events: &[NoteEvent<()>], tempo_map: &TempoMap, options: &Options,
+                 num_samples: u32, max_tail: u32) -> Result<Vec<NoteEvent<()>>, String>
+    where P: HeadlessPlugin + Plugin<SysExMessage = ()> {
+    let mut host = TestHost::new(options.block_size);
+    host.transport.sample_rate = options.sample_rate;
+    host.transport.playing = true;
+    if !host.initialize(plugin) {
+        return Err(format!("{} cannot run at a sample rate of {} Hz with blocks of {} samples", P::NAME,
+                           options.sample_rate, options.block_size));
+    }
+
+    for event in events {
+        host.queue_event(*event);
+    }
+
+    let tempo_changes = tempo_map.changes_in_samples(options.sample_rate);
+    for (idx, (_, tempo)) in tempo_changes.iter().enumerate() {
+        host.transport.tempo = Some(*tempo);
+        let segment_end = tempo_changes.get(idx + 1)
+            .map_or(num_samples, |(next, _)| (*next).min(num_samples as u64) as u32);
+        if segment_end > host.position() {
+            host.run(plugin, segment_end - host.position());
+        }
+    }
+
+    let tail_end = num_samples.saturating_add(max_tail);
+    while host.position() < tail_end && is_sounding(host.output_events()) {
+        host.run(plugin, options.block_size.min(tail_end - host.position()));
+    }
+    host.transport.playing = false;
+    host.run(plugin, options.block_size);
+
+    Ok(host.take_output_events())
+}
+
+/// Whether a note of the events is on and has not been ended yet.
+fn is_sounding(events: &[NoteEvent<()>]) -> bool {
+    let mut sounding = [0i32; 16 * 128];
+    for event in events {
+        match event {
+            NoteEvent::NoteOn { channel, note, .. } => sounding[*channel as usize * 128 + *note as usize] += 1,
+            NoteEvent::NoteOff { channel, note, .. } => sounding[*channel as usize * 128 + *note as usize] -= 1,
+            _ => {}
+        }
+    }
+    sounding.iter().any(|count| *count > 0)
+}
@@ -0,0 +1,273 @@
+use midly::num::{u14, u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, PitchBend, Smf, Timing, TrackEvent, TrackEventKind};
+use nih_plug::prelude::NoteEvent;
+use std::path::Path;
+
+const MICROSECONDS_PER_MINUTE: f64 = 60_000_000.0;
+
+/// The tempo changes of a MIDI file, to convert between ticks and samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    pub ticks_per_beat: u16,
+    /// tick and tempo in bpm, sorted by tick. There is always a tempo at tick 0.
+    changes: Vec<(u64, f64)>,
+}
+
+impl TempoMap {
+    pub fn new(ticks_per_beat: u16, tempo: f64) -> Self {
+        Self {
+            ticks_per_beat,
+            changes: vec![(0, tempo)],
+        }
+    }
+
+    pub fn set_tempo(&mut self, tick: u64, tempo: f64) {
+        match self.changes.binary_search_by_key(&tick, |(change_tick, _)| *change_tick) {
+            Ok(idx) => self.changes[idx].1 = tempo,
+            Err(idx) => self.changes.insert(idx, (tick, tempo)),
+        }
+    }
+
+    pub fn changes(&self) -> &[(u64, f64)] {
+        &self.changes
+    }
+
+    /// The sample positions the tempo changes, with the tempo from there on.
+    pub fn changes_in_samples(&self, sample_rate: f32) -> Vec<(u64, f64)> {
+        self.changes.iter().map(|(tick, tempo)| (self.tick_to_sample(*tick, sample_rate), *tempo)).collect()
+    }
+
+    pub fn tick_to_sample(&self, tick: u64, sample_rate: f32) -> u64 {
+        let mut seconds = 0.0;
+        for (idx, (start, tempo)) in self.changes.iter().enumerate() {
+            if *start >= tick {
+                break;
+            }
+            let end = self.changes.get(idx + 1).map_or(tick, |(next, _)| (*next).min(tick));
+            seconds += self.ticks_to_seconds(end - start, *tempo);
+        }
+        (seconds * sample_rate as f64).round() as u64
+    }
+
+    pub fn sample_to_tick(&self, sample: u64, sample_rate: f32) -> u64 {
+        let mut seconds = sample as f64 / sample_rate as f64;
+        for (idx, (start, tempo)) in self.changes.iter().enumerate() {
+            let segment_seconds = self.changes.get(idx + 1)
+                .map(|(next, _)| self.ticks_to_seconds(next - start, *tempo));
+
+            match segment_seconds {
+                Some(segment_seconds) if seconds >= segment_seconds => seconds -= segment_seconds,
+                _ => return start + (seconds / 60.0 * tempo * self.ticks_per_beat as f64).round() as u64,
+            }
+        }
+        0
+    }
+
+    fn ticks_to_seconds(&self, ticks: u64, tempo: f64) -> f64 {
+        ticks as f64 / self.ticks_per_beat as f64 * 60.0 / tempo
+    }
+}
+
+/// The MIDI events of all tracks of a MIDI file, with the tick they happen at.
+pub struct MidiFile {
+    pub tempo_map: TempoMap,
+    pub events: Vec<(u64, NoteEvent<()>)>,
+}
+
+/// Reads a Standard MIDI File. The tracks are merged, events of the same tick keep the order of the tracks.
+/// `tempo` is used if the file has no tempo of its own.
+pub fn read_midi_file(path: &Path, tempo: f64) -> Result<MidiFile, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let smf = Smf::parse(&bytes).map_err(|e| format!("Cannot parse {}: {}", path.display(), e))?;
+
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int(),
+        Timing::Timecode(..) => return Err(format!("{}: SMPTE timing is not supported", path.display())),
+    };
+
+    let mut midi_file = MidiFile {
+        tempo_map: TempoMap::new(ticks_per_beat, tempo),
+        events: vec![],
+    };
+
+    for track in smf.tracks.iter() {
+        let mut tick = 0;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(microseconds_per_beat)) => {
+                    midi_file.tempo_map.set_tempo(tick, MICROSECONDS_PER_MINUTE / microseconds_per_beat.as_int() as f64);
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    if let Some(note_event) = midi_to_event(0, channel, message) {
+                        midi_file.events.push((tick, note_event));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    midi_file.events.sort_by_key(|(tick, _)| *tick);
+
+    Ok(midi_file)
+}
+
+/// Writes the events as a single track MIDI file, together with the tempo changes.
+pub fn write_midi_file(path: &Path, tempo_map: &TempoMap, events: &[(u64, NoteEvent<()>)]) -> Result<(), String> {
+    let mut timed_events: Vec<(u64, TrackEventKind)> = tempo_map.changes().iter()
+        .map(|(tick, tempo)| {
+            let microseconds_per_beat = (MICROSECONDS_PER_MINUTE / tempo).round() as u32;
+            (*tick, TrackEventKind::Meta(MetaMessage::Tempo(u24::new(microseconds_per_beat))))
+        })
+        .collect();
+    timed_events.extend(events.iter().filter_map(|(tick, event)| {
+        event_to_midi(event).map(|(channel, message)| (*tick, TrackEventKind::Midi { channel, message }))
+    }));
+    // the tempo changes come first, otherwise the order of the events is kept
+    timed_events.sort_by_key(|(tick, _)| *tick);
+
+    let mut track = vec![];
+    let mut last_tick = 0;
+    for (tick, kind) in timed_events {
+        track.push(TrackEvent { delta: u28::new((tick - last_tick) as u32), kind });
+        last_tick = tick;
+    }
+    track.push(TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(tempo_map.ticks_per_beat))));
+    smf.tracks.push(track);
+    smf.save(path).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+pub fn midi_to_event(timing: u32, channel: u4, message: MidiMessage) -> Option<NoteEvent<()>> {
+    let channel = channel.as_int();
+    let normalize = |value: u7| value.as_int() as f32 / 127.0;
+
+    match message {
+        // by convention a NoteOn without velocity is a NoteOff
+        MidiMessage::NoteOn { key, vel } if vel.as_int() == 0 => {
+            Some(NoteEvent::NoteOff { timing, voice_id: None, channel, note: key.as_int(), velocity: 0.0 })
+        }
+        MidiMessage::NoteOn { key, vel } => {
+            Some(NoteEvent::NoteOn { timing, voice_id: None, channel, note: key.as_int(), velocity: normalize(vel) })
+        }
+        MidiMessage::NoteOff { key, vel } => {
+            Some(NoteEvent::NoteOff { timing, voice_id: None, channel, note: key.as_int(), velocity: normalize(vel) })
+        }
+        MidiMessage::Aftertouch { key, vel } => {
+            Some(NoteEvent::PolyPressure { timing, voice_id: None, channel, note: key.as_int(), pressure: normalize(vel) })
+        }
+        MidiMessage::Controller { controller, value } => {
+            Some(NoteEvent::MidiCC { timing, channel, cc: controller.as_int(), value: normalize(value) })
+        }
+        MidiMessage::ProgramChange { program } => {
+            Some(NoteEvent::MidiProgramChange { timing, channel, program: program.as_int() })
+        }
+        MidiMessage::ChannelAftertouch { vel } => {
+            Some(NoteEvent::MidiChannelPressure { timing, channel, pressure: normalize(vel) })
+        }
+        MidiMessage::PitchBend { bend } => {
+            Some(NoteEvent::MidiPitchBend { timing, channel, value: bend.0.as_int() as f32 / 16383.0 })
+        }
+    }
+}
+
+pub fn event_to_midi(event: &NoteEvent<()>) -> Option<(u4, MidiMessage)> {
+    let to_u7 = |value: f32| u7::new((value.clamp(0.0, 1.0) * 127.0).round() as u8);
+
+    match *event {
+        NoteEvent::NoteOn { channel, note, velocity, .. } => {
+            // a NoteOn must not be written as a NoteOff
+            let vel = u7::new(to_u7(velocity).as_int().max(1));
+            Some((u4::new(channel), MidiMessage::NoteOn { key: u7::new(note), vel }))
+        }
+        NoteEvent::NoteOff { channel, note, velocity, .. } => {
+            Some((u4::new(channel), MidiMessage::NoteOff { key: u7::new(note), vel: to_u7(velocity) }))
+        }
+        NoteEvent::PolyPressure { channel, note, pressure, .. } => {
+            Some((u4::new(channel), MidiMessage::Aftertouch { key: u7::new(note), vel: to_u7(pressure) }))
+        }
+        NoteEvent::MidiCC { channel, cc, value, .. } => {
+            Some((u4::new(channel), MidiMessage::Controller { controller: u7::new(cc), value: to_u7(value) }))
+        }
+        NoteEvent::MidiProgramChange { channel, program, .. } => {
+            Some((u4::new(channel), MidiMessage::ProgramChange { program: u7::new(program) }))
+        }
+        NoteEvent::MidiChannelPressure { channel, pressure, .. } => {
+            Some((u4::new(channel), MidiMessage::ChannelAftertouch { vel: to_u7(pressure) }))
+        }
+        NoteEvent::MidiPitchBend { channel, value, .. } => {
+            let bend = PitchBend(u14::new((value.clamp(0.0, 1.0) * 16383.0).round() as u16));
+            Some((u4::new(channel), MidiMessage::PitchBend { bend }))
+        }
+        _ => None,
+    }
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use midly::num::{u4, u7};
+    use midly::MidiMessage;
+    use nih_plug::prelude::NoteEvent;
+    use crate::midi::{event_to_midi, midi_to_event, read_midi_file, write_midi_file, TempoMap};
+
+    #[test]
+    fn test_tempo_map() {
+        let mut tempo_map = TempoMap::new(480, 120.0);
+        assert_eq!(tempo_map.tick_to_sample(480, 44_100.0), 22_050);
+        assert_eq!(tempo_map.sample_to_tick(22_050, 44_100.0), 480);
+
+        // twice as slow from the second beat on
+        tempo_map.set_tempo(480, 60.0);
+        assert_eq!(tempo_map.tick_to_sample(480, 44_100.0), 22_050);
+        assert_eq!(tempo_map.tick_to_sample(960, 44_100.0), 66_150);
+        assert_eq!(tempo_map.sample_to_tick(66_150, 44_100.0), 960);
+        assert_eq!(tempo_map.sample_to_tick(11_025, 44_100.0), 240);
+        assert_eq!(tempo_map.changes_in_samples(44_100.0), [(0, 120.0), (22_050, 60.0)]);
+
+        // a tempo at the same tick replaces the old one
+        tempo_map.set_tempo(0, 240.0);
+        assert_eq!(tempo_map.changes(), [(0, 240.0), (480, 60.0)]);
+    }
+
+    #[test]
+    fn test_event_conversion() {
+        let note_on = NoteEvent::NoteOn { timing: 0, voice_id: None, channel: 15, note: 60, velocity: 1.0 };
+        let (channel, message) = event_to_midi(&note_on).unwrap();
+        assert_eq!(channel, u4::new(15));
+        assert_eq!(midi_to_event(0, channel, message), Some(note_on));
+
+        let cc = NoteEvent::MidiCC { timing: 0, channel: 0, cc: 64, value: 1.0 };
+        let (channel, message) = event_to_midi(&cc).unwrap();
+        assert_eq!(midi_to_event(0, channel, message), Some(cc));
+
+        // a NoteOn without velocity is a NoteOff
+        assert_eq!(midi_to_event(10, u4::new(0), MidiMessage::NoteOn { key: u7::new(60), vel: u7::new(0) }),
+                   Some(NoteEvent::NoteOff { timing: 10, voice_id: None, channel: 0, note: 60, velocity: 0.0 }));
+    }
+
+    #[test]
+    fn test_write_read() {
+        // unique for every run of the tests
+        let path = std::env::temp_dir().join(format!("modular_render_test_write_read_{}.mid", std::process::id()));
+
+        let mut tempo_map = TempoMap::new(96, 100.0);
+        tempo_map.set_tempo(192, 150.0);
+        let events = vec![
+            (0, NoteEvent::NoteOn { timing: 0, voice_id: None, channel: 0, note: 60, velocity: 1.0 }),
+            (96, NoteEvent::NoteOff { timing: 0, voice_id: None, channel: 0, note: 60, velocity: 1.0 }),
+            (200, NoteEvent::NoteOn { timing: 0, voice_id: None, channel: 15, note: 64, velocity: 1.0 }),
+        ];
+        write_midi_file(&path, &tempo_map, &events).unwrap();
+
+        let midi_file = read_midi_file(&path, 120.0).unwrap();
+        assert_eq!(midi_file.tempo_map, tempo_map);
+        assert_eq!(midi_file.events, events);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use nih_plug::prelude::Plugin;
+use modular_host::set_parameter;
+use std::path::Path;
+
+/// Parses a preset. Every line sets a parameter by its id to a plain value, e.g. `chord_channel = 1`. Empty lines and
+/// lines starting with `#` are ignored.
+pub fn parse_preset(preset: &str) -> Result<Vec<(String, f32)>, String> {
+    preset.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_setting)
+        .collect()
+}
+
+pub fn read_preset(path: &Path) -> Result<Vec<(String, f32)>, String> {
+    let preset = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    parse_preset(&preset)
+}
+
+/// Parses a single `id = value` setting.
+pub fn parse_setting(setting: &str) -> Result<(String, f32), String> {
+    let (id, value) = setting.split_once('=').ok_or_else(|| format!("Expected id = value: {}", setting))?;
+    let value = value.trim().parse::<f32>().map_err(|_| format!("Not a number: {}", setting))?;
+    Ok((id.trim().to_string(), value))
+}
+
+pub fn apply_preset<P: Plugin>(plugin: &P, preset: &[(String, f32)]) -> Result<(), String> {
+    for (id, value) in preset {
+        if !set_parameter(plugin, id, *value) {
+            return Err(format!("Unknown parameter: {}", id));
+        }
+    }
+    Ok(())
+}
+
+
+// Tests
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::preset::{parse_preset, parse_setting};
+
+    #[test]
+    fn test_parse_preset() {
+        let preset = "# bass\nchord_channel = 1\n\n  root_note=36  \n";
+        assert_eq!(parse_preset(preset).unwrap(), [("chord_channel".to_string(), 1.0), ("root_note".to_string(), 36.0)]);
+
+        assert!(parse_preset("chord_channel 1").is_err());
+        assert!(parse_preset("chord_channel = one").is_err());
+    }
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(parse_setting("latch=1").unwrap(), ("latch".to_string(), 1.0));
+    }
+}